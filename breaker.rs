@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Externally visible state of a circuit breaker
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    // Calls go through
+    Closed,
+    // Calls are refused until the cooldown has passed
+    Open,
+    // Cooldown passed; a single probe call is let through to test recovery
+    HalfOpen,
+}
+
+struct Inner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+// Opens after `threshold` consecutive failures so callers fail fast instead of waiting on a
+// struggling dependency, then lets one probe through per cooldown to see if it recovered
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold: threshold.max(1),
+            cooldown,
+            inner: Mutex::new(Inner {
+                consecutive_failures: 0,
+                opened_at: None,
+                probing: false,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let inner = self.inner.lock().unwrap();
+        match inner.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    // A permit when a call may proceed; in the half-open state only the first caller gets to probe
+    pub fn allow(&self) -> Option<Permit<'_>> {
+        let mut inner = self.inner.lock().unwrap();
+        let probe = match inner.opened_at {
+            None => false,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => return None,
+            Some(_) if inner.probing => return None,
+            Some(_) => {
+                inner.probing = true;
+                true
+            }
+        };
+        Some(Permit {
+            breaker: self,
+            probe,
+            settled: false,
+        })
+    }
+
+    fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = 0;
+        inner.opened_at = None;
+        inner.probing = false;
+    }
+
+    fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        // A failed probe restarts the cooldown
+        if inner.probing || inner.consecutive_failures >= self.threshold {
+            inner.opened_at = Some(Instant::now());
+            inner.probing = false;
+        }
+    }
+}
+
+// Leave to make one call, taken from `allow`. Report the outcome with `succeeded` or `failed`; a
+// probe dropped without an outcome, e.g. because the request was cancelled mid-call, frees the
+// half-open slot for the next caller instead of leaving the breaker waiting on it forever
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    probe: bool,
+    settled: bool,
+}
+
+impl Permit<'_> {
+    pub fn succeeded(mut self) {
+        self.settled = true;
+        self.breaker.record_success();
+    }
+
+    pub fn failed(mut self) {
+        self.settled = true;
+        self.breaker.record_failure();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.probe && !self.settled {
+            self.breaker.inner.lock().unwrap().probing = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_breaker_opens_then_half_opens_after_cooldown() {
+        let breaker = CircuitBreaker::new(3, Duration::from_millis(30));
+        let mut store_calls = 0;
+        // Stands in for a store that is down: every call that gets through fails
+        let mut call_failing_store = |breaker: &CircuitBreaker| {
+            let Some(permit) = breaker.allow() else {
+                return false;
+            };
+            store_calls += 1;
+            permit.failed();
+            true
+        };
+
+        for _ in 0..3 {
+            assert!(call_failing_store(&breaker));
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!call_failing_store(&breaker), "open breaker short-circuits");
+
+        actix_web::rt::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(call_failing_store(&breaker), "one probe is let through");
+        assert_eq!(breaker.state(), BreakerState::Open, "failed probe reopens");
+        assert_eq!(store_calls, 4);
+
+        actix_web::rt::time::sleep(Duration::from_millis(40)).await;
+        let probe = breaker.allow();
+        assert!(probe.is_some());
+        assert!(breaker.allow().is_none(), "only one probe at a time");
+        probe.unwrap().succeeded();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[actix_web::test]
+    async fn test_cancelled_probe_frees_the_half_open_slot() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(10));
+        breaker.allow().unwrap().failed();
+        actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+
+        // A probe whose future is dropped mid-call, like a request whose client went away
+        let stuck = std::future::pending::<()>();
+        let probe = async {
+            let _permit = breaker.allow().unwrap();
+            stuck.await;
+        };
+        assert!(actix_web::rt::time::timeout(Duration::from_millis(5), probe).await.is_err());
+
+        let next = breaker.allow();
+        assert!(next.is_some(), "the next caller gets to probe");
+        next.unwrap().succeeded();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+}
@@ -0,0 +1,185 @@
+use lru::LruCache;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Values computed from the store, reused for as long as the store's revision is unchanged.
+// Entries from older revisions are only served through `lookup` within its stale window, and
+// are dropped when the cache fills
+pub struct RevisionCache<K, V> {
+    entries: Mutex<HashMap<K, Entry<V>>>,
+    capacity: usize,
+    hits: AtomicU64,
+}
+
+struct Entry<V> {
+    revision: u64,
+    value: V,
+    // When a lookup first found the entry behind the store's revision
+    stale_since: Option<Instant>,
+}
+
+// How a value returned by `lookup` relates to the current revision
+#[derive(Debug, PartialEq)]
+pub enum Freshness {
+    Fresh,
+    // Cached for an earlier revision; `refresh` is set for the one caller that should recompute it
+    Stale { refresh: bool },
+}
+
+impl<K: Hash + Eq, V: Clone> RevisionCache<K, V> {
+    // A cache holding at most `capacity` entries; zero disables caching
+    pub fn new(capacity: usize) -> Self {
+        RevisionCache {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    // The value cached for the key at this revision. With a non-zero `stale_for`, a value from an
+    // earlier revision is still returned, as stale, for up to `stale_for` after a lookup first found
+    // it out of date; the first such lookup is told to refresh it
+    pub fn lookup(&self, key: &K, revision: u64, stale_for: Duration) -> Option<(V, Freshness)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?;
+        let freshness = if entry.revision == revision {
+            Freshness::Fresh
+        } else if stale_for.is_zero() {
+            return None;
+        } else {
+            let refresh = entry.stale_since.is_none();
+            let stale_since = *entry.stale_since.get_or_insert_with(Instant::now);
+            if stale_since.elapsed() >= stale_for {
+                return None;
+            }
+            Freshness::Stale { refresh }
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some((entry.value.clone(), freshness))
+    }
+
+    pub fn insert(&self, key: K, revision: u64, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.revision == revision);
+            if entries.len() >= self.capacity {
+                entries.clear();
+            }
+        }
+        entries.insert(key, Entry {
+            revision,
+            value,
+            stale_since: None,
+        });
+    }
+
+    // Number of lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+// Values kept by key until they are invalidated or pushed out by more recently used ones. Unlike
+// RevisionCache, entries survive unrelated writes, so writers must invalidate the keys they change
+pub struct RecentlyUsed<K, V> {
+    // None when the capacity is zero, which disables caching
+    entries: Option<Mutex<LruCache<K, V>>>,
+    hits: AtomicU64,
+}
+
+impl<K: Hash + Eq, V: Clone> RecentlyUsed<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        RecentlyUsed {
+            entries: NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.entries.as_ref()?.lock().unwrap().get(key).cloned()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().put(key, value);
+        }
+    }
+
+    pub fn invalidate(&self, key: &K) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(key);
+        }
+    }
+
+    // Drop every entry, for writes that may touch any key
+    pub fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().clear();
+        }
+    }
+
+    // Number of gets answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_entries_expire_with_the_revision() {
+        let cache = RevisionCache::new(2);
+        let get = |key, revision| cache.lookup(key, revision, Duration::ZERO).map(|(value, _)| value);
+        cache.insert("a", 1, 10);
+        assert_eq!(get(&"a", 1), Some(10));
+        assert_eq!(get(&"a", 2), None);
+
+        cache.insert("b", 2, 20);
+        cache.insert("c", 2, 30);
+        // The stale entry made room for the current ones
+        assert_eq!(get(&"a", 1), None);
+        assert_eq!((get(&"b", 2), get(&"c", 2)), (Some(20), Some(30)));
+        assert_eq!(cache.hits(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_stale_entries_are_served_within_the_window() {
+        let cache = RevisionCache::new(2);
+        cache.insert("a", 1, 10);
+        assert_eq!(cache.lookup(&"a", 2, Duration::from_secs(60)), Some((10, Freshness::Stale { refresh: true })));
+        assert_eq!(cache.lookup(&"a", 2, Duration::from_secs(60)), Some((10, Freshness::Stale { refresh: false })));
+        assert_eq!(cache.lookup(&"a", 2, Duration::from_millis(1)).map(|(value, _)| value), Some(10));
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(cache.lookup(&"a", 2, Duration::from_millis(1)), None);
+        cache.insert("a", 2, 20);
+        assert_eq!(cache.lookup(&"a", 2, Duration::from_secs(60)), Some((20, Freshness::Fresh)));
+    }
+
+    #[actix_web::test]
+    async fn test_least_recently_used_entry_is_evicted() {
+        let cache = RecentlyUsed::new(2);
+        cache.insert(1, "Dune");
+        cache.insert(2, "Emma");
+        assert_eq!(cache.get(&1), Some("Dune"));
+        cache.insert(3, "Ubik");
+        assert_eq!((cache.get(&1), cache.get(&2), cache.get(&3)), (Some("Dune"), None, Some("Ubik")));
+        cache.invalidate(&1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.hits(), 3);
+
+        let disabled = RecentlyUsed::new(0);
+        disabled.insert(1, "Dune");
+        assert_eq!(disabled.get(&1), None);
+    }
+}
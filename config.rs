@@ -0,0 +1,434 @@
+use actix_web::http::KeepAlive;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Longest keep-alive accepted; idle connections held longer mostly pin file descriptors
+const MAX_KEEP_ALIVE_SECS: u64 = 3600;
+
+// Keep-alive and client timeouts for the HttpServer, derived from Config
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ServerTimeouts {
+    pub keep_alive: KeepAlive,
+    pub client_request_timeout: Duration,
+    pub client_disconnect_timeout: Duration,
+}
+
+// Body format of successful and error responses, chosen per request from Accept
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseFormat {
+    Json,
+    Xml,
+}
+
+impl FromStr for ResponseFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "json" => Ok(ResponseFormat::Json),
+            "xml" => Ok(ResponseFormat::Xml),
+            _ => Err(format!("unknown response format {:?}, expected json or xml", value)),
+        }
+    }
+}
+
+// Normalization of request paths before routing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailingSlashMode {
+    // Collapse repeated slashes and drop a trailing one, so "/books/" routes like "/books"
+    Trim,
+    // Only collapse repeated slashes
+    Merge,
+    Off,
+}
+
+impl FromStr for TrailingSlashMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "trim" => Ok(TrailingSlashMode::Trim),
+            "merge" => Ok(TrailingSlashMode::Merge),
+            "off" => Ok(TrailingSlashMode::Off),
+            _ => Err(format!("unknown trailing slash mode {:?}, expected trim, merge or off", value)),
+        }
+    }
+}
+
+// Runtime configuration resolved from environment variables
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    // JSON file books are loaded from at startup and flushed to periodically and on shutdown; in-memory only when unset
+    pub data_file: Option<PathBuf>,
+    // Seconds between background flushes of changed books to data_file
+    pub flush_interval_secs: u64,
+    // PEM certificate chain and private key; HTTPS is served only when both are set
+    pub tls_cert_path: Option<PathBuf>,
+    pub tls_key_path: Option<PathBuf>,
+    // Directory cover images are stored in, one file per book id
+    pub cover_dir: PathBuf,
+    // Largest cover image accepted, in bytes
+    pub cover_max_bytes: usize,
+    // max-age sent in Cache-Control on successful GET /books and GET /books/{id} responses
+    pub cache_max_age_secs: u64,
+    // Format used when a request has no Accept header or accepts anything
+    pub default_content_type: ResponseFormat,
+    // Send X-Content-Type-Options: nosniff and X-Frame-Options: DENY on every response
+    pub security_headers: bool,
+    // How request paths are normalized before routing
+    pub trailing_slash: TrailingSlashMode,
+    // max-age of the Strict-Transport-Security header sent while serving TLS; 0 leaves it out
+    pub hsts_max_age_secs: u64,
+    // Seconds in-flight requests get to finish before connections are force-closed
+    pub shutdown_timeout_secs: u64,
+    // Seconds an idle connection is kept open for another request; 0 closes it after each response
+    pub keep_alive_secs: u64,
+    // Milliseconds a client has to send the request head once connected
+    pub client_request_timeout_ms: u64,
+    // Milliseconds given to a client to acknowledge the connection being closed; 0 waits indefinitely
+    pub client_disconnect_timeout_ms: u64,
+    // Namespace put in front of rendered ids, e.g. "lib1" for ids like "lib1-00042"; path ids must carry it
+    pub id_prefix: Option<String>,
+    // Ordering applied to the list endpoint when no sort parameter is given, e.g. "title:asc"
+    pub default_sort: String,
+    // Milliseconds read endpoints wait for the store lock before answering 503
+    pub store_read_timeout_ms: u64,
+    // Consecutive store read failures that open the circuit breaker, and how long it stays open
+    pub store_breaker_threshold: u32,
+    pub store_breaker_cooldown_secs: u64,
+    // Reject unrecognized query parameters on every request, not only those passing strict=true
+    pub strict_query: bool,
+    // Reject JSON bodies that aren't UTF-8 (400) and Content-Type charsets other than utf-8 (415)
+    pub validate_body_encoding: bool,
+    // Most books a q search returns before the rest are dropped
+    pub max_search_results: usize,
+    // Distinct list queries whose results are kept until the next write; 0 disables the cache
+    pub list_cache_entries: usize,
+    // Books kept by id for single-book reads, least recently used first out; 0 disables the cache
+    pub book_cache_capacity: usize,
+    // Seconds an outdated list cache entry is still served while it is recomputed in the background; 0 never serves one
+    pub list_cache_swr_secs: u64,
+    // Let updates give a book the title and author of another book of the same owner and tenant
+    pub allow_duplicates: bool,
+    // Maximum Levenshtein distance accepted by fuzzy search
+    pub fuzzy_max_distance: usize,
+    // Start with writes frozen
+    pub maintenance_mode: bool,
+    // Key required in the X-Api-Key header for /admin endpoints; admin is disabled when unset
+    pub admin_api_key: Option<String>,
+    // Per-caller API keys mapped to the principal name they authenticate as; when set,
+    // every request must carry a known key and non-admin callers only see their own books
+    pub api_keys: HashMap<String, String>,
+    // Require an X-Tenant-Id header and keep each tenant's books separate
+    pub tenant_isolation: bool,
+    // Requests allowed per client per minute; rate limiting is off when unset
+    pub rate_limit_per_minute: Option<u32>,
+    // Most books a tenant (or, without tenant isolation, the whole store) may hold; unlimited when unset
+    pub max_books: Option<usize>,
+    // Origins allowed to make cross-origin requests ("*" for any); CORS is off when empty
+    pub cors_allowed_origins: Vec<String>,
+    // Request headers cross-origin callers may send ("*" for any)
+    pub cors_allowed_headers: Vec<String>,
+    // Seconds browsers may cache a preflight response
+    pub cors_max_age: usize,
+    // Send Access-Control-Allow-Credentials so browsers include cookies
+    pub cors_allow_credentials: bool,
+    // Responses smaller than this many bytes are sent uncompressed
+    pub compress_min_bytes: u64,
+    // Requests taking longer than this many milliseconds are logged at WARN level
+    pub slow_request_ms: u64,
+    // Request paths left out of the access log, e.g. health probes
+    pub log_skip_paths: Vec<String>,
+    // Seed for GET /books/random, for repeatable picks; drawn from entropy when unset
+    pub random_seed: Option<u64>,
+    // Genres books may be given, matched ignoring case; any genre is accepted while empty
+    pub genre_allowlist: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_file: None,
+            flush_interval_secs: 30,
+            tls_cert_path: None,
+            tls_key_path: None,
+            cover_dir: PathBuf::from("covers"),
+            cover_max_bytes: 2 * 1024 * 1024,
+            cache_max_age_secs: 0,
+            default_content_type: ResponseFormat::Json,
+            security_headers: true,
+            trailing_slash: TrailingSlashMode::Trim,
+            hsts_max_age_secs: 31_536_000,
+            shutdown_timeout_secs: 30,
+            keep_alive_secs: 5,
+            client_request_timeout_ms: 5000,
+            client_disconnect_timeout_ms: 1000,
+            id_prefix: None,
+            default_sort: "id:asc".to_string(),
+            store_read_timeout_ms: 2000,
+            store_breaker_threshold: 5,
+            store_breaker_cooldown_secs: 30,
+            strict_query: false,
+            validate_body_encoding: true,
+            max_search_results: 500,
+            list_cache_entries: 256,
+            book_cache_capacity: 1024,
+            list_cache_swr_secs: 0,
+            allow_duplicates: false,
+            fuzzy_max_distance: 2,
+            maintenance_mode: false,
+            admin_api_key: None,
+            api_keys: HashMap::new(),
+            tenant_isolation: false,
+            rate_limit_per_minute: None,
+            max_books: None,
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_headers: vec!["Content-Type".to_string(), "Authorization".to_string()],
+            cors_max_age: 3600,
+            cors_allow_credentials: false,
+            compress_min_bytes: 1024,
+            slow_request_ms: 1000,
+            log_skip_paths: Vec::new(),
+            random_seed: None,
+            genre_allowlist: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    // Build the configuration from the process environment
+    pub fn from_env() -> Result<Config, String> {
+        Config::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    // Build the configuration from an arbitrary variable lookup
+    pub fn from_lookup<F: Fn(&str) -> Option<String>>(lookup: F) -> Result<Config, String> {
+        let defaults = Config::default();
+        let config = Config {
+            data_file: parse_optional_var(&lookup, "DATA_FILE")?,
+            flush_interval_secs: parse_var(&lookup, "FLUSH_INTERVAL_SECS", defaults.flush_interval_secs)?,
+            tls_cert_path: parse_optional_var(&lookup, "TLS_CERT_PATH")?,
+            tls_key_path: parse_optional_var(&lookup, "TLS_KEY_PATH")?,
+            cover_dir: parse_var(&lookup, "COVER_DIR", defaults.cover_dir)?,
+            cover_max_bytes: parse_var(&lookup, "COVER_MAX_BYTES", defaults.cover_max_bytes)?,
+            cache_max_age_secs: parse_var(&lookup, "CACHE_MAX_AGE_SECS", defaults.cache_max_age_secs)?,
+            default_content_type: parse_var(&lookup, "DEFAULT_CONTENT_TYPE", defaults.default_content_type)?,
+            security_headers: parse_var(&lookup, "SECURITY_HEADERS", defaults.security_headers)?,
+            trailing_slash: parse_var(&lookup, "TRAILING_SLASH", defaults.trailing_slash)?,
+            hsts_max_age_secs: parse_var(&lookup, "HSTS_MAX_AGE_SECS", defaults.hsts_max_age_secs)?,
+            shutdown_timeout_secs: parse_var(&lookup, "SHUTDOWN_TIMEOUT_SECS", defaults.shutdown_timeout_secs)?,
+            keep_alive_secs: parse_var(&lookup, "KEEP_ALIVE_SECS", defaults.keep_alive_secs)?,
+            client_request_timeout_ms: parse_var(&lookup, "CLIENT_REQUEST_TIMEOUT_MS", defaults.client_request_timeout_ms)?,
+            client_disconnect_timeout_ms: parse_var(&lookup, "CLIENT_DISCONNECT_TIMEOUT_MS", defaults.client_disconnect_timeout_ms)?,
+            id_prefix: lookup("ID_PREFIX").filter(|prefix| !prefix.is_empty()),
+            default_sort: lookup("DEFAULT_SORT").filter(|sort| !sort.trim().is_empty()).unwrap_or(defaults.default_sort),
+            store_read_timeout_ms: parse_var(&lookup, "STORE_READ_TIMEOUT_MS", defaults.store_read_timeout_ms)?,
+            store_breaker_threshold: parse_var(&lookup, "STORE_BREAKER_THRESHOLD", defaults.store_breaker_threshold)?,
+            store_breaker_cooldown_secs: parse_var(&lookup, "STORE_BREAKER_COOLDOWN_SECS", defaults.store_breaker_cooldown_secs)?,
+            strict_query: parse_var(&lookup, "STRICT_QUERY", defaults.strict_query)?,
+            validate_body_encoding: parse_var(&lookup, "VALIDATE_BODY_ENCODING", defaults.validate_body_encoding)?,
+            max_search_results: parse_var(&lookup, "MAX_SEARCH_RESULTS", defaults.max_search_results)?,
+            list_cache_entries: parse_var(&lookup, "LIST_CACHE_ENTRIES", defaults.list_cache_entries)?,
+            book_cache_capacity: parse_var(&lookup, "BOOK_CACHE_CAPACITY", defaults.book_cache_capacity)?,
+            list_cache_swr_secs: parse_var(&lookup, "LIST_CACHE_SWR_SECS", defaults.list_cache_swr_secs)?,
+            allow_duplicates: parse_var(&lookup, "ALLOW_DUPLICATES", defaults.allow_duplicates)?,
+            fuzzy_max_distance: parse_var(&lookup, "FUZZY_MAX_DISTANCE", defaults.fuzzy_max_distance)?,
+            maintenance_mode: parse_var(&lookup, "MAINTENANCE_MODE", defaults.maintenance_mode)?,
+            admin_api_key: lookup("ADMIN_API_KEY").filter(|key| !key.is_empty()),
+            api_keys: parse_api_keys(lookup("API_KEYS").as_deref().unwrap_or(""))?,
+            tenant_isolation: parse_var(&lookup, "TENANT_ISOLATION", defaults.tenant_isolation)?,
+            rate_limit_per_minute: parse_optional_var(&lookup, "RATE_LIMIT_PER_MINUTE")?,
+            max_books: parse_optional_var(&lookup, "MAX_BOOKS")?,
+            cors_allowed_origins: parse_list(lookup("CORS_ALLOWED_ORIGINS")),
+            cors_allowed_headers: lookup("CORS_ALLOWED_HEADERS").map_or(defaults.cors_allowed_headers, |headers| parse_list(Some(headers))),
+            cors_max_age: parse_var(&lookup, "CORS_MAX_AGE", defaults.cors_max_age)?,
+            cors_allow_credentials: parse_var(&lookup, "CORS_ALLOW_CREDENTIALS", defaults.cors_allow_credentials)?,
+            compress_min_bytes: parse_var(&lookup, "COMPRESS_MIN_BYTES", defaults.compress_min_bytes)?,
+            slow_request_ms: parse_var(&lookup, "SLOW_REQUEST_MS", defaults.slow_request_ms)?,
+            log_skip_paths: parse_list(lookup("LOG_SKIP_PATHS")),
+            random_seed: parse_optional_var(&lookup, "RANDOM_SEED")?,
+            genre_allowlist: parse_list(lookup("GENRE_ALLOWLIST")),
+        };
+        if let Err(e) = crate::handlers::parse_sort(&config.default_sort) {
+            return Err(format!("invalid value for DEFAULT_SORT: {}", e));
+        }
+        if let Some(prefix) = config.id_prefix.as_deref().filter(|prefix| !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')) {
+            return Err(format!("invalid value for ID_PREFIX: {:?} may only contain letters, digits and '_'", prefix));
+        }
+        if config.flush_interval_secs == 0 {
+            return Err("FLUSH_INTERVAL_SECS must be greater than zero".to_string());
+        }
+        // actix treats 0 as no timeout, which would let slow clients hold connections open
+        if config.client_request_timeout_ms == 0 {
+            return Err("CLIENT_REQUEST_TIMEOUT_MS must be greater than zero".to_string());
+        }
+        if config.keep_alive_secs > MAX_KEEP_ALIVE_SECS {
+            return Err(format!("KEEP_ALIVE_SECS must be at most {}", MAX_KEEP_ALIVE_SECS));
+        }
+        if config.tls_cert_path.is_some() != config.tls_key_path.is_some() {
+            return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string());
+        }
+        if let Some(name) = config
+            .cors_allowed_headers
+            .iter()
+            .find(|name| *name != "*" && actix_web::http::header::HeaderName::try_from(name.as_str()).is_err())
+        {
+            return Err(format!("invalid value for CORS_ALLOWED_HEADERS: {:?} is not a header name", name));
+        }
+        // Browsers refuse credentialed responses for a wildcard origin, and echoing any origin instead would be unsafe
+        if config.cors_allow_credentials && config.cors_allowed_origins.iter().any(|origin| origin == "*") {
+            return Err("CORS_ALLOW_CREDENTIALS cannot be combined with a \"*\" origin in CORS_ALLOWED_ORIGINS".to_string());
+        }
+        Ok(config)
+    }
+
+    // Connection settings applied to the HttpServer builder
+    pub fn server_timeouts(&self) -> ServerTimeouts {
+        ServerTimeouts {
+            keep_alive: match self.keep_alive_secs {
+                0 => KeepAlive::Disabled,
+                secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+            },
+            client_request_timeout: Duration::from_millis(self.client_request_timeout_ms),
+            client_disconnect_timeout: Duration::from_millis(self.client_disconnect_timeout_ms),
+        }
+    }
+
+    // Certificate and key paths when the server should listen with TLS, None for plain HTTP
+    pub fn tls_paths(&self) -> Option<(&Path, &Path)> {
+        Some((self.tls_cert_path.as_deref()?, self.tls_key_path.as_deref()?))
+    }
+}
+
+// Parse a single variable, falling back to the default when it is unset
+pub fn parse_var<T: FromStr, F: Fn(&str) -> Option<String>>(lookup: &F, name: &str, default: T) -> Result<T, String> {
+    match lookup(name) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid value for {}: {:?}", name, value)),
+        None => Ok(default),
+    }
+}
+
+// Split a comma-separated variable into its non-empty entries
+fn parse_list(value: Option<String>) -> Vec<String> {
+    value
+        .map(|value| {
+            value
+                .split(',')
+                .map(|entry| entry.trim().to_string())
+                .filter(|entry| !entry.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Parse "key:principal" pairs separated by commas
+fn parse_api_keys(value: &str) -> Result<HashMap<String, String>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((key, principal)) if !key.trim().is_empty() && !principal.trim().is_empty() => {
+                Ok((key.trim().to_string(), principal.trim().to_string()))
+            }
+            _ => Err(format!("invalid value for API_KEYS: entry {:?} must look like key:principal", entry)),
+        })
+        .collect()
+}
+
+// Parse a variable that has no default; unset or empty means None
+fn parse_optional_var<T: FromStr, F: Fn(&str) -> Option<String>>(lookup: &F, name: &str) -> Result<Option<T>, String> {
+    match lookup(name).filter(|value| !value.trim().is_empty()) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid value for {}: {:?}", name, value)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_config_shutdown_timeout_from_env() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        assert_eq!(config.shutdown_timeout_secs, 30);
+
+        let config = Config::from_lookup(|name| (name == "SHUTDOWN_TIMEOUT_SECS").then(|| "5".to_string())).unwrap();
+        assert_eq!(config.shutdown_timeout_secs, 5);
+
+        let err = Config::from_lookup(|name| (name == "SHUTDOWN_TIMEOUT_SECS").then(|| "soon".to_string())).unwrap_err();
+        assert!(err.contains("SHUTDOWN_TIMEOUT_SECS"));
+    }
+
+    #[actix_web::test]
+    async fn test_config_rejects_wildcard_cors_with_credentials() {
+        let lookup = |origins: &'static str| {
+            move |name: &str| match name {
+                "CORS_ALLOWED_ORIGINS" => Some(origins.to_string()),
+                "CORS_ALLOW_CREDENTIALS" => Some("true".to_string()),
+                _ => None,
+            }
+        };
+        let err = Config::from_lookup(lookup("https://app.example, *")).unwrap_err();
+        assert!(err.contains("CORS_ALLOW_CREDENTIALS"));
+
+        let config = Config::from_lookup(lookup("https://app.example")).unwrap();
+        assert_eq!(config.cors_allowed_origins, vec!["https://app.example".to_string()]);
+        assert!(config.cors_allow_credentials);
+        assert_eq!(config.cors_max_age, 3600);
+    }
+
+    #[actix_web::test]
+    async fn test_config_selects_tls_when_both_paths_are_set() {
+        let config = Config::from_lookup(|_| None).unwrap();
+        assert_eq!(config.tls_paths(), None);
+
+        let config = Config::from_lookup(|name| match name {
+            "TLS_CERT_PATH" => Some("/etc/tls/cert.pem".to_string()),
+            "TLS_KEY_PATH" => Some("/etc/tls/key.pem".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.tls_paths(), Some((Path::new("/etc/tls/cert.pem"), Path::new("/etc/tls/key.pem"))));
+
+        let err = Config::from_lookup(|name| (name == "TLS_CERT_PATH").then(|| "/etc/tls/cert.pem".to_string())).unwrap_err();
+        assert!(err.contains("TLS_KEY_PATH"));
+    }
+
+    #[actix_web::test]
+    async fn test_config_maps_connection_timeouts() {
+        let timeouts = Config::from_lookup(|_| None).unwrap().server_timeouts();
+        assert_eq!(timeouts, ServerTimeouts {
+            keep_alive: KeepAlive::Timeout(Duration::from_secs(5)),
+            client_request_timeout: Duration::from_millis(5000),
+            client_disconnect_timeout: Duration::from_millis(1000),
+        });
+
+        let config = Config::from_lookup(|name| match name {
+            "KEEP_ALIVE_SECS" => Some("0".to_string()),
+            "CLIENT_REQUEST_TIMEOUT_MS" => Some("250".to_string()),
+            "CLIENT_DISCONNECT_TIMEOUT_MS" => Some("0".to_string()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.server_timeouts(), ServerTimeouts {
+            keep_alive: KeepAlive::Disabled,
+            client_request_timeout: Duration::from_millis(250),
+            client_disconnect_timeout: Duration::ZERO,
+        });
+
+        let err = Config::from_lookup(|name| (name == "CLIENT_REQUEST_TIMEOUT_MS").then(|| "0".to_string())).unwrap_err();
+        assert!(err.contains("CLIENT_REQUEST_TIMEOUT_MS"));
+        let err = Config::from_lookup(|name| (name == "KEEP_ALIVE_SECS").then(|| "86400".to_string())).unwrap_err();
+        assert!(err.contains("KEEP_ALIVE_SECS"));
+    }
+}
@@ -0,0 +1,356 @@
+use actix_web::error::{JsonPayloadError, QueryPayloadError};
+use actix_web::http::{header, StatusCode};
+use crate::middleware::{MAINTENANCE_RETRY_AFTER_SECS, TENANT_HEADER};
+use crate::store::StoreError;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use log::error;
+use std::fmt;
+
+// Seconds clients are asked to wait after a read gave up on a contended store
+pub const STORE_BUSY_RETRY_AFTER_SECS: u64 = 1;
+
+// Error returned by handlers, rendered as {"error":{"code":...,"message":...}}
+#[derive(Debug)]
+pub enum ApiError {
+    EmptyBody,
+    InvalidJson(String),
+    // Schema version header that is malformed or names an unsupported version
+    InvalidSchemaVersion(String),
+    // Query parameter whose value could not be parsed
+    InvalidParameter(String),
+    // Body that could not be read, outside of JSON parsing
+    InvalidBody(String),
+    // Body of a media type the endpoint does not take
+    UnsupportedMediaType(String),
+    // Non-JSON body over the endpoint's own size limit
+    PayloadTooLarge(String),
+    // JSON body whose bytes are not UTF-8
+    InvalidEncoding(String),
+    // Content-Type charset other than utf-8
+    UnsupportedCharset(String),
+    // Body larger than the limit once any Content-Encoding is undone
+    BodyTooLarge,
+    // Well-formed body that breaks a field rule
+    Validation(String),
+    // Malformed BCP 47 language tag in a body or query
+    InvalidLanguage(String),
+    // Book kind outside the known set
+    InvalidKind(String),
+    // Path id without the configured ID_PREFIX, or otherwise malformed
+    InvalidId(String),
+    // Query parameters the endpoint does not recognize, reported in strict mode
+    UnknownParameters(Vec<String>),
+    // Tenant isolation is on and the request names no tenant
+    MissingTenant,
+    // Missing or unknown API key
+    Unauthorized,
+    NotFound(String),
+    Conflict(String),
+    // If-Match names a version other than the stored one
+    PreconditionFailed(String),
+    // A lookup that should name one book matched all of these
+    Ambiguous(Vec<i32>),
+    // Ids listed more than once where each may appear only once
+    DuplicateIds(Vec<i32>),
+    // Ids that name no book the caller can see
+    UnknownIds(Vec<i32>),
+    // A create would take the collection past MAX_BOOKS
+    QuotaExceeded(String),
+    // Client exceeded the rate limit; carries the seconds until its window resets
+    RateLimited(u64),
+    // Store could not serve the request in time; clients should retry
+    StoreUnavailable(String),
+    // Maintenance mode is on and the request would write
+    Maintenance,
+    // Unexpected failure whose details are logged rather than returned
+    Internal(String),
+}
+
+// Central mapping of store failures to HTTP errors
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        match err {
+            StoreError::NotFound => ApiError::NotFound(err.to_string()),
+            StoreError::Conflict => ApiError::Conflict(err.to_string()),
+            StoreError::Timeout => ApiError::StoreUnavailable(err.to_string()),
+            StoreError::Backend(_) => ApiError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl ApiError {
+    // Book ids the error is about, sent along with the code and message
+    pub fn ids(&self) -> Option<&[i32]> {
+        match self {
+            ApiError::Ambiguous(ids) | ApiError::DuplicateIds(ids) | ApiError::UnknownIds(ids) => Some(ids),
+            _ => None,
+        }
+    }
+
+    // Stable machine-readable code clients can match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::EmptyBody => "empty_body",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::InvalidSchemaVersion(_) => "invalid_schema_version",
+            ApiError::InvalidParameter(_) => "invalid_parameter",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::UnsupportedMediaType(_) => "unsupported_media_type",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::InvalidEncoding(_) => "invalid_encoding",
+            ApiError::UnsupportedCharset(_) => "unsupported_charset",
+            ApiError::BodyTooLarge => "body_too_large",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::InvalidLanguage(_) => "invalid_language",
+            ApiError::InvalidKind(_) => "invalid_kind",
+            ApiError::InvalidId(_) => "invalid_id",
+            ApiError::UnknownParameters(_) => "unknown_parameters",
+            ApiError::MissingTenant => "missing_tenant",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::PreconditionFailed(_) => "precondition_failed",
+            ApiError::Maintenance => "maintenance",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Ambiguous(_) => "ambiguous",
+            ApiError::DuplicateIds(_) => "duplicate_ids",
+            ApiError::UnknownIds(_) => "unknown_ids",
+            ApiError::QuotaExceeded(_) => "quota_exceeded",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::StoreUnavailable(_) => "store_unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::EmptyBody => write!(f, "request body must not be empty"),
+            ApiError::InvalidJson(message) => write!(f, "invalid JSON body: {}", message),
+            ApiError::InvalidEncoding(message) => write!(f, "request body is not valid UTF-8: {}", message),
+            ApiError::UnsupportedCharset(charset) => write!(f, "unsupported charset {:?}, only utf-8 is accepted", charset),
+            ApiError::BodyTooLarge => write!(f, "request body must be at most {} bytes after decompression", MAX_JSON_BODY_BYTES),
+            ApiError::InvalidSchemaVersion(message) | ApiError::InvalidParameter(message) | ApiError::InvalidBody(message) | ApiError::UnsupportedMediaType(message) | ApiError::PayloadTooLarge(message) => write!(f, "{}", message),
+            ApiError::Validation(message) | ApiError::InvalidId(message) | ApiError::NotFound(message) | ApiError::Conflict(message) | ApiError::PreconditionFailed(message) | ApiError::QuotaExceeded(message) | ApiError::StoreUnavailable(message) => {
+                write!(f, "{}", message)
+            }
+            ApiError::RateLimited(secs) => write!(f, "rate limit exceeded, retry in {}s", secs),
+            ApiError::MissingTenant => write!(f, "missing {} header", TENANT_HEADER),
+            ApiError::Unauthorized => write!(f, "invalid or missing API key"),
+            ApiError::Maintenance => write!(f, "service is in maintenance mode, writes are temporarily disabled"),
+            ApiError::Ambiguous(ids) => write!(f, "{} books match", ids.len()),
+            ApiError::Internal(_) => write!(f, "internal server error"),
+            ApiError::InvalidLanguage(tag) => write!(f, "invalid language tag: {:?}", tag),
+            ApiError::InvalidKind(kind) => write!(f, "unknown kind {:?}, expected \"book\" or \"magazine\"", kind),
+            ApiError::UnknownParameters(names) => write!(f, "unrecognized query parameters: {}", names.join(", ")),
+            ApiError::DuplicateIds(ids) => write!(f, "book ids listed more than once: {:?}", ids),
+            ApiError::UnknownIds(ids) => write!(f, "unknown book ids: {:?}", ids),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::EmptyBody
+            | ApiError::InvalidJson(_)
+            | ApiError::InvalidEncoding(_)
+            | ApiError::InvalidBody(_)
+            | ApiError::InvalidParameter(_)
+            | ApiError::InvalidSchemaVersion(_)
+            | ApiError::InvalidLanguage(_)
+            | ApiError::InvalidKind(_)
+            | ApiError::InvalidId(_)
+            | ApiError::MissingTenant
+            | ApiError::DuplicateIds(_)
+            | ApiError::UnknownIds(_)
+            | ApiError::UnknownParameters(_) => StatusCode::BAD_REQUEST,
+            ApiError::BodyTooLarge | ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedCharset(_) | ApiError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            ApiError::Conflict(_) | ApiError::Ambiguous(_) => StatusCode::CONFLICT,
+            ApiError::QuotaExceeded(_) => StatusCode::FORBIDDEN,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::StoreUnavailable(_) | ApiError::Maintenance => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            ApiError::StoreUnavailable(_) => {
+                response.insert_header((header::RETRY_AFTER, STORE_BUSY_RETRY_AFTER_SECS.to_string()));
+            }
+            ApiError::RateLimited(secs) => {
+                response.insert_header((header::RETRY_AFTER, secs.to_string()));
+            }
+            ApiError::Maintenance => {
+                response.insert_header((header::RETRY_AFTER, MAINTENANCE_RETRY_AFTER_SECS.to_string()));
+            }
+            ApiError::Internal(message) => error!("internal error: {}", message),
+            _ => {}
+        }
+        let details = ErrorDetails {
+            code: self.code(),
+            message: self.to_string(),
+            ids: self.ids().map(<[i32]>::to_vec),
+        };
+        let mut error = serde_json::json!({
+            "code": details.code,
+            "message": details.message,
+        });
+        if let Some(ids) = &details.ids {
+            error["ids"] = serde_json::json!(ids);
+        }
+        let mut response = response.json(serde_json::json!({ "error": error }));
+        // Kept so the problem_details middleware can re-render the error for clients asking for it
+        response.extensions_mut().insert(details);
+        response
+    }
+}
+
+// Media type of RFC 7807 Problem Details, sent when a client lists it in Accept
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+// Code and message of an ApiError, attached to the extensions of its response
+#[derive(Clone, Debug)]
+pub struct ErrorDetails {
+    pub code: &'static str,
+    pub message: String,
+    pub ids: Option<Vec<i32>>,
+}
+
+impl ErrorDetails {
+    // RFC 7807 body for this error; "about:blank" makes the title the status phrase, and the
+    // stable code, and any ids, travel as extension members
+    pub fn to_problem(&self, status: StatusCode, instance: &str) -> serde_json::Value {
+        let mut problem = serde_json::json!({
+            "type": "about:blank",
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": self.message,
+            "instance": instance,
+            "code": self.code,
+        });
+        if let Some(ids) = &self.ids {
+            problem["ids"] = serde_json::json!(ids);
+        }
+        problem
+    }
+}
+
+// Whether the request declared no body at all
+fn has_empty_body(req: &HttpRequest) -> bool {
+    let content_length = req.headers().get(header::CONTENT_LENGTH).and_then(|v| v.to_str().ok());
+    let chunked = req.headers().contains_key(header::TRANSFER_ENCODING);
+    match content_length {
+        Some(length) => length.trim() == "0",
+        None => !chunked,
+    }
+}
+
+// Map JSON extractor failures to ApiError: empty or malformed bodies are 400, well-formed ones of the wrong shape 422
+fn json_error_handler(err: JsonPayloadError, req: &HttpRequest) -> actix_web::Error {
+    let empty = match &err {
+        // Nothing was read before hitting end of input
+        JsonPayloadError::Deserialize(e) => e.is_eof() && e.line() == 1 && e.column() == 0,
+        _ => has_empty_body(req),
+    };
+    if empty {
+        ApiError::EmptyBody.into()
+    } else if matches!(&err, JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. }) {
+        ApiError::BodyTooLarge.into()
+    } else if matches!(&err, JsonPayloadError::Deserialize(e) if e.is_data()) {
+        // Valid JSON of the wrong shape, e.g. a missing field or a string where a number belongs
+        ApiError::Validation(err.to_string()).into()
+    } else {
+        ApiError::InvalidJson(err.to_string()).into()
+    }
+}
+
+// Largest JSON body accepted; gzip, deflate, brotli and zstd bodies are decompressed by the
+// extractor and the limit applies to the decompressed bytes, which stops decompression bombs
+pub const MAX_JSON_BODY_BYTES: usize = 1024 * 1024;
+
+// JSON extractor configuration shared by all endpoints taking a body
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().limit(MAX_JSON_BODY_BYTES).error_handler(json_error_handler)
+}
+
+// Query extractor configuration shared by all endpoints: a missing required parameter or a value
+// of the wrong type or outside an enum is a 400 in the error envelope
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err: QueryPayloadError, _| ApiError::InvalidParameter(err.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::create_book;
+    use crate::store::{Books, Store};
+    use actix_web::{test, App};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_store_errors_map_to_statuses() {
+        let cases = [
+            (StoreError::NotFound, StatusCode::NOT_FOUND, "not_found"),
+            (StoreError::Conflict, StatusCode::CONFLICT, "conflict"),
+            (StoreError::Backend("disk full".to_string()), StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+            (StoreError::Timeout, StatusCode::SERVICE_UNAVAILABLE, "store_unavailable"),
+        ];
+        for (store_error, status, code) in cases {
+            let res = ApiError::from(store_error).error_response();
+            assert_eq!(res.status(), status);
+            assert!(res.headers().contains_key(header::RETRY_AFTER) == (status == StatusCode::SERVICE_UNAVAILABLE));
+            let body: serde_json::Value = serde_json::from_slice(&actix_web::body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+            assert_eq!(body["error"]["code"], code);
+            // Backend details stay in the logs
+            assert!(!body.to_string().contains("disk full"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_gzipped_bodies_are_decompressed_and_capped() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let post = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/books")
+                .insert_header((header::CONTENT_TYPE, "application/json"))
+                .insert_header((header::CONTENT_ENCODING, "gzip"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let res = test::call_service(&app, post(gzip(br#"{"title": "Dune", "author": "Frank Herbert"}"#))).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(store.read().await.books[0].title, "Dune");
+
+        let res = test::call_service(&app, post(b"not gzip at all".to_vec())).await;
+        assert_eq!(res.status(), 400);
+
+        // Compresses to a few kilobytes but inflates past the limit
+        let bomb = format!(r#"{{"title": "{}", "author": "Frank Herbert"}}"#, " ".repeat(MAX_JSON_BODY_BYTES));
+        let res = test::call_service(&app, post(gzip(bomb.as_bytes()))).await;
+        assert_eq!(res.status(), 413);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "body_too_large");
+        assert_eq!(store.read().await.books.len(), 1);
+    }
+}
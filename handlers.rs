@@ -0,0 +1,4339 @@
+use crate::breaker::{BreakerState, CircuitBreaker};
+use crate::cache::{Freshness, RecentlyUsed, RevisionCache};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::jobs::Jobs;
+use crate::logging::LogLevel;
+use crate::middleware::{AuthContext, Maintenance, Principal, Tenant};
+use crate::models::*;
+use crate::random::Rng;
+use crate::patch;
+use crate::search::tokenize;
+use crate::singleflight::SingleFlight;
+use crate::store::{normalize, Books, Store, StoreError};
+use tokio::sync::RwLockReadGuard;
+use actix_web::http::header;
+use actix_web::http::header::ContentType;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, ResponseError};
+use log::{info, warn, LevelFilter};
+use schemars::schema_for;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::{ready, Ready};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug_span, Instrument};
+
+// Query parameters accepted by the list endpoint
+#[derive(Deserialize, Default, Clone)]
+pub struct ListQuery {
+    // Case-insensitive search over title and author
+    pub q: Option<String>,
+    // Also match title and author tokens within a small edit distance of the query
+    pub fuzzy: Option<bool>,
+    // Comma-separated sort keys with optional direction, e.g. "author:asc,title:desc"
+    pub sort: Option<String>,
+    // Render ids as JSON strings for clients that cannot represent large integers
+    pub id_as_string: Option<bool>,
+    // Page size; when set, Range headers are ignored and Link headers are added
+    pub limit: Option<usize>,
+    // Number of matching books to skip
+    pub offset: Option<usize>,
+    // Wrap the page as {"data": [...], "pagination": {...}}
+    pub envelope: Option<bool>,
+    // Only books tagged with this BCP 47 language or one of its subtags
+    pub language: Option<String>,
+    // Only books carrying this tag, ignoring case
+    pub tag: Option<String>,
+}
+
+// Largest page the list endpoint serves for an explicit limit
+const MAX_LIST_LIMIT: usize = 1000;
+
+// Parameters understood by the list endpoint, checked in strict mode
+const LIST_QUERY_PARAMS: [&str; 10] = ["q", "fuzzy", "sort", "id_as_string", "limit", "offset", "envelope", "language", "tag", "strict"];
+
+// In strict mode (STRICT_QUERY or ?strict=true) reject query parameters outside the known set
+fn check_query_params(req: &HttpRequest, known: &[&str]) -> Result<(), ApiError> {
+    let params = web::Query::<Vec<(String, String)>>::from_query(req.query_string()).map_or_else(|_| Vec::new(), |query| query.into_inner());
+    let strict = req.app_data::<web::Data<Config>>().is_some_and(|config| config.strict_query)
+        || params.iter().any(|(name, value)| name == "strict" && value == "true");
+    if !strict {
+        return Ok(());
+    }
+    let mut unknown: Vec<String> = Vec::new();
+    for (name, _) in params {
+        if !known.contains(&name.as_str()) && !unknown.contains(&name) {
+            unknown.push(name);
+        }
+    }
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::UnknownParameters(unknown))
+    }
+}
+
+// Query parameters controlling how ids are rendered in single-book responses
+#[derive(Deserialize)]
+pub struct IdFormatQuery {
+    pub id_as_string: Option<bool>,
+    // Embed HAL-style `_links` to the book's own resources
+    pub hateoas: Option<bool>,
+    // Comma-separated related data to embed; "author_books" adds the author's other books
+    pub include: Option<String>,
+    // Most embedded author_books, DEFAULT_AUTHOR_BOOKS_LIMIT when unset
+    pub author_books_limit: Option<usize>,
+}
+
+// Default and maximum number of books embedded by ?include=author_books
+const DEFAULT_AUTHOR_BOOKS_LIMIT: usize = 10;
+const MAX_AUTHOR_BOOKS_LIMIT: usize = 100;
+
+// Representation an update responds with
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReturnMode {
+    // The whole updated book, the default
+    #[default]
+    Full,
+    // Only the id and the fields whose values changed
+    Changed,
+}
+
+// Query parameters accepted by the update endpoints
+#[derive(Deserialize)]
+pub struct UpdateQuery {
+    #[serde(default, rename = "return")]
+    pub return_mode: ReturnMode,
+}
+
+// Fields every update changes, left out of changed-only responses (in both serde casings)
+const BOOKKEEPING_FIELDS: [&str; 3] = ["updated_at", "updatedAt", "version"];
+
+// Id of a book and the fields that differ between two of its states, as rendered in responses
+fn changed_fields(before: &Book, after: &Book) -> serde_json::Result<serde_json::Value> {
+    let (serde_json::Value::Object(old), serde_json::Value::Object(new)) = (serde_json::to_value(before)?, serde_json::to_value(after)?) else {
+        return serde_json::to_value(after);
+    };
+    let mut changed = serde_json::Map::new();
+    // Fields left out when unset, e.g. tags, are reported as null once cleared
+    let fields = new.keys().chain(old.keys().filter(|field| !new.contains_key(*field)));
+    for field in fields {
+        let value = new.get(field).cloned().unwrap_or(serde_json::Value::Null);
+        if field == "id" || (!BOOKKEEPING_FIELDS.contains(&field.as_str()) && old.get(field) != Some(&value)) {
+            changed.insert(field.clone(), value);
+        }
+    }
+    Ok(serde_json::Value::Object(changed))
+}
+
+// Links from a book to the requests that act on it; there are no author resources to link to yet
+fn book_links(id: i32, prefix: Option<&str>) -> serde_json::Value {
+    let href = format!("/books/{}", external_id(id.into(), prefix));
+    serde_json::json!({
+        "self": {"href": href},
+        "update": {"href": href, "method": "PUT"},
+        "delete": {"href": href, "method": "DELETE"},
+    })
+}
+
+// Fields holding lists of book ids, rendered like "id" fields
+const ID_LIST_FIELDS: [&str; 2] = ["ids", "missing"];
+
+// Replace numeric "id" fields, and the numbers of id list fields, with their string form, recursively
+fn stringify_ids(value: &mut serde_json::Value, prefix: Option<&str>) {
+    match value {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| stringify_ids(item, prefix)),
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                match field {
+                    serde_json::Value::Number(number) if name == "id" => {
+                        if let Some(id) = number.as_i64() {
+                            *field = serde_json::Value::String(external_id(id, prefix));
+                        }
+                    }
+                    serde_json::Value::Array(ids) if ID_LIST_FIELDS.contains(&name.as_str()) => {
+                        for id in ids.iter_mut() {
+                            if let Some(number) = id.as_i64() {
+                                *id = serde_json::Value::String(external_id(number, prefix));
+                            }
+                        }
+                    }
+                    _ => stringify_ids(field, prefix),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Rendered form of an id: the number, or the configured ID_PREFIX and the zero-padded number
+fn external_id(id: i64, prefix: Option<&str>) -> String {
+    match prefix {
+        Some(prefix) => format!("{}-{:05}", prefix, id),
+        None => id.to_string(),
+    }
+}
+
+// Serialize a response body, rendering ids as strings when requested or prefixed when ID_PREFIX is set
+fn to_json_body<T: Serialize>(value: &T, id_as_string: bool, prefix: Option<&str>) -> serde_json::Result<String> {
+    if !id_as_string && prefix.is_none() {
+        return serde_json::to_string(value);
+    }
+    let mut value = serde_json::to_value(value)?;
+    stringify_ids(&mut value, prefix);
+    serde_json::to_string(&value)
+}
+
+// The configured ID_PREFIX, if any
+fn id_prefix(req: &HttpRequest) -> Option<&str> {
+    req.app_data::<web::Data<Config>>().and_then(|config| config.id_prefix.as_deref())
+}
+
+// Finish a response with a JSON body whose book ids are rendered with the configured ID_PREFIX,
+// so every id a client is given can be used in a /books/{id} path
+fn json_with_ids<T: Serialize>(req: &HttpRequest, response: &mut HttpResponseBuilder, value: &T) -> HttpResponse {
+    match to_json_body(value, false, id_prefix(req)) {
+        Ok(body) => response.content_type(ContentType::json()).body(body),
+        Err(e) => ApiError::Internal(e.to_string()).error_response(),
+    }
+}
+
+// Book id taken from the {id} path segment. With ID_PREFIX set it must read "<prefix>-<number>",
+// and anything else is a 400; without one a non-numeric id is simply not found
+pub struct BookId(i32);
+
+impl std::ops::Deref for BookId {
+    type Target = i32;
+
+    fn deref(&self) -> &i32 {
+        &self.0
+    }
+}
+
+impl FromRequest for BookId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let raw = req.match_info().get("id").unwrap_or("");
+        let id = match id_prefix(req) {
+            None => raw.parse().map_err(|_| ApiError::from(StoreError::NotFound).into()),
+            Some(prefix) => match raw.strip_prefix(prefix).and_then(|rest| rest.strip_prefix('-')) {
+                Some(number) => number.parse().map_err(|_| ApiError::InvalidId(format!("malformed id {:?}", raw)).into()),
+                None => Err(ApiError::InvalidId(format!("id {:?} does not start with \"{}-\"", raw, prefix)).into()),
+            },
+        };
+        ready(id.map(BookId))
+    }
+}
+
+// Fields the list endpoint can be sorted by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortKey {
+    Id,
+    Title,
+    Author,
+    UpdatedAt,
+    Position,
+}
+
+// Direction applied to a single sort key
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+// Parse a sort spec like "author:asc,title:desc" into ordered keys
+pub fn parse_sort(spec: &str) -> Result<Vec<(SortKey, SortDirection)>, String> {
+    spec.split(',')
+        .map(|part| {
+            let (key, direction) = part.split_once(':').unwrap_or((part, "asc"));
+            let key = match key.trim() {
+                "id" => SortKey::Id,
+                "title" => SortKey::Title,
+                "author" => SortKey::Author,
+                "updated_at" => SortKey::UpdatedAt,
+                "position" => SortKey::Position,
+                other => return Err(format!("invalid sort key: {:?}", other)),
+            };
+            let direction = match direction.trim() {
+                "asc" => SortDirection::Asc,
+                "desc" => SortDirection::Desc,
+                other => return Err(format!("invalid sort direction: {:?}", other)),
+            };
+            Ok((key, direction))
+        })
+        .collect()
+}
+
+// Compare two books by each sort key in turn
+fn compare_books(a: &Book, b: &Book, keys: &[(SortKey, SortDirection)]) -> Ordering {
+    keys.iter()
+        .map(|(key, direction)| {
+            let ordering = match key {
+                SortKey::Id => a.id.cmp(&b.id),
+                SortKey::Title => a.title.cmp(&b.title),
+                SortKey::Author => a.author.cmp(&b.author),
+                SortKey::UpdatedAt => a.updated_at.cmp(&b.updated_at),
+                // Books outside the reading list sort after every positioned one
+                SortKey::Position => (a.position.is_none(), a.position).cmp(&(b.position.is_none(), b.position)),
+            };
+            match direction {
+                SortDirection::Asc => ordering,
+                SortDirection::Desc => ordering.reverse(),
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+// How well a book matched a search query, best first
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    Exact,
+    Fuzzy,
+}
+
+// Rank a book against a search query, or None when it does not match
+fn match_book(book: &Book, query: &str, fuzzy: bool, max_distance: usize) -> Option<MatchRank> {
+    let query = query.to_lowercase();
+    if book.title.to_lowercase().contains(&query) || book.author.to_lowercase().contains(&query) {
+        return Some(MatchRank::Exact);
+    }
+    if !fuzzy {
+        return None;
+    }
+    let book_tokens: Vec<String> = tokenize(&book.title).chain(tokenize(&book.author)).collect();
+    let mut query_tokens = tokenize(&query).peekable();
+    query_tokens.peek()?;
+    query_tokens
+        .all(|q| book_tokens.iter().any(|t| strsim::levenshtein(&q, t) <= max_distance))
+        .then_some(MatchRank::Fuzzy)
+}
+
+// Whether a book passes a bulk-operation filter
+fn filter_matches(filter: &BookFilter, book: &Book, config: &Config) -> bool {
+    filter.q.as_deref().is_none_or(|q| match_book(book, q, false, config.fuzzy_max_distance).is_some())
+        && filter.author.as_deref().is_none_or(|author| normalize(&book.author) == normalize(author))
+        && filter.title.as_deref().is_none_or(|title| normalize(&book.title) == normalize(title))
+}
+
+// Default and maximum number of books returned by the recent books feed
+const DEFAULT_RECENT_LIMIT: usize = 10;
+const MAX_RECENT_LIMIT: usize = 100;
+
+// Query parameters accepted by the recent books endpoint
+#[derive(Deserialize)]
+pub struct RecentQuery {
+    pub limit: Option<usize>,
+}
+
+// Default and maximum number of books returned by the longest titles endpoint
+const DEFAULT_LONGEST_TITLES_LIMIT: usize = 10;
+const MAX_LONGEST_TITLES_LIMIT: usize = 100;
+
+// Query parameters accepted by the longest titles endpoint
+#[derive(Deserialize)]
+pub struct LongestTitlesQuery {
+    pub limit: Option<usize>,
+}
+
+// Default and maximum page size of the browse endpoint
+const DEFAULT_BROWSE_LIMIT: usize = 50;
+const MAX_BROWSE_LIMIT: usize = 500;
+
+// Field whose initial places a book in the A-Z browse
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BrowseBy {
+    // The last word of the author's name, the default
+    #[default]
+    Surname,
+    Title,
+}
+
+impl BrowseBy {
+    // Uppercased first letter of the field, None when it is empty
+    fn initial(self, book: &Book) -> Option<char> {
+        let value = match self {
+            BrowseBy::Surname => book.author.split_whitespace().last().unwrap_or(""),
+            BrowseBy::Title => book.title.trim(),
+        };
+        value.chars().next().and_then(|c| c.to_uppercase().next())
+    }
+}
+
+// Query parameters accepted by the browse endpoint
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    pub letter: String,
+    #[serde(default)]
+    pub by: BrowseBy,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+// Query parameters accepted by the browse index endpoint
+#[derive(Deserialize)]
+pub struct BrowseIndexQuery {
+    #[serde(default)]
+    pub by: BrowseBy,
+}
+
+// Default and maximum number of similar books returned
+const DEFAULT_SIMILAR_LIMIT: usize = 10;
+const MAX_SIMILAR_LIMIT: usize = 100;
+
+// Query parameters accepted by the similar books endpoint
+#[derive(Deserialize)]
+pub struct SimilarQuery {
+    pub limit: Option<usize>,
+}
+
+// Query parameters accepted by the natural key lookup endpoint
+#[derive(Deserialize)]
+pub struct LookupQuery {
+    pub title: String,
+    pub author: String,
+}
+
+// Recommended fields the incomplete books endpoint can check for
+const COMPLETENESS_FIELDS: [&str; 3] = ["isbn", "genre", "year"];
+
+// Query parameters accepted by the incomplete books endpoint
+#[derive(Deserialize)]
+pub struct IncompleteQuery {
+    // Comma-separated fields a book must have, from COMPLETENESS_FIELDS; all of them when unset
+    pub require: Option<String>,
+}
+
+// Query parameters accepted by the count by author endpoint
+#[derive(Deserialize)]
+pub struct CountByAuthorQuery {
+    // Only the top N authors
+    pub limit: Option<usize>,
+}
+
+// Maximum number of author suggestions returned
+const MAX_AUTHOR_SUGGESTIONS: usize = 10;
+
+// Query parameters accepted by the author suggestion endpoint
+#[derive(Deserialize)]
+pub struct SuggestQuery {
+    pub prefix: Option<String>,
+}
+
+// What an import does with a book whose id is already taken
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    Skip,
+    Overwrite,
+    // Reject the whole import, the default
+    #[default]
+    Error,
+}
+
+// Query parameters accepted by the import endpoint
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy,
+    // Answer 202 with a job to poll instead of waiting for the import
+    #[serde(default, rename = "async")]
+    pub run_async: bool,
+}
+
+// Query parameters accepted by the dedupe and normalize-authors endpoints
+#[derive(Deserialize)]
+pub struct DedupeQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+// Look up a preference like "return" in the request's Prefer header (RFC 7240)
+fn preference(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get_all("Prefer")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|pref| {
+            let pref = pref.split(';').next().unwrap_or("").trim();
+            let (key, value) = pref.split_once('=').unwrap_or((pref, ""));
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"').to_lowercase())
+        })
+        .next()
+}
+
+// Everything that determines which books a list request matches, so identical
+// concurrent requests can share one store read
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ListFlightKey {
+    q: Option<String>,
+    fuzzy: Option<bool>,
+    sort: Option<String>,
+    language: Option<String>,
+    tag: Option<String>,
+    principal: Option<Principal>,
+    tenant: Option<Tenant>,
+}
+
+impl ListFlightKey {
+    fn new(query: &ListQuery, ctx: &AuthContext) -> Self {
+        ListFlightKey {
+            q: query.q.clone(),
+            fuzzy: query.fuzzy,
+            sort: query.sort.clone(),
+            language: query.language.clone(),
+            tag: query.tag.clone(),
+            principal: ctx.principal.clone(),
+            tenant: ctx.tenant.clone(),
+        }
+    }
+}
+
+// In-flight list reads, registered as app data to coalesce thundering herds on /books
+pub type ListFlights = SingleFlight<ListFlightKey, Result<Arc<LoadedBooks>, StoreError>>;
+
+// List results cached per query until the store changes, registered as app data
+pub type ListCache = RevisionCache<ListFlightKey, Arc<LoadedBooks>>;
+
+// Books served by get_book, cached by id and registered as app data. Writers invalidate the ids
+// they change before releasing the store's write lock, and reads fill the cache while holding the
+// read lock, so a read racing a write can't put back an old copy
+pub type BookCache = RecentlyUsed<i32, Book>;
+
+// Invalidate cached copies of the given books
+fn forget_books(req: &HttpRequest, ids: impl IntoIterator<Item = i32>) {
+    if let Some(cache) = req.app_data::<web::Data<BookCache>>() {
+        ids.into_iter().for_each(|id| cache.invalidate(&id));
+    }
+}
+
+// Invalidate every cached book, for writes that may touch any of them
+fn forget_all_books(req: &HttpRequest) {
+    if let Some(cache) = req.app_data::<web::Data<BookCache>>() {
+        cache.clear();
+    }
+}
+
+// Books matched by a list request, and whether a search hit MAX_SEARCH_RESULTS
+pub struct LoadedBooks {
+    books: Vec<Book>,
+    truncated: bool,
+}
+
+// Header set when search results were cut at MAX_SEARCH_RESULTS
+pub const RESULTS_TRUNCATED_HEADER: &str = "X-Results-Truncated";
+
+// Take the store's read lock, giving up after the configured timeout so readers don't queue
+// forever behind writers. Timeouts feed the store circuit breaker when one is registered, and
+// while it is open reads fail straight away
+async fn read_store<'a>(req: &HttpRequest, books: &'a Books) -> Result<RwLockReadGuard<'a, Store>, StoreError> {
+    let timeout_ms = req
+        .app_data::<web::Data<Config>>()
+        .map_or(Config::default().store_read_timeout_ms, |config| config.store_read_timeout_ms);
+    // An open breaker fails fast, reported like the timeouts that opened it. The permit is held
+    // across the wait, so a request cancelled mid-probe gives the probe back when it is dropped
+    let permit = match req.app_data::<web::Data<CircuitBreaker>>() {
+        Some(breaker) => Some(breaker.allow().ok_or(StoreError::Timeout)?),
+        None => None,
+    };
+    let store = tokio::time::timeout(Duration::from_millis(timeout_ms), books.read()).await.map_err(|_| StoreError::Timeout);
+    if let Some(permit) = permit {
+        match store {
+            Ok(_) => permit.succeeded(),
+            Err(_) => permit.failed(),
+        }
+    }
+    store
+}
+
+// Validate a write payload against the schema version negotiated for the request;
+// a bad version header or language tag is a 400, a payload breaking the rules a 422
+fn validate_payload(req: &HttpRequest, new_book: &NewBook) -> Result<(), actix_web::Error> {
+    let version = SchemaVersion::from_request(req).map_err(ApiError::InvalidSchemaVersion)?;
+    let genre_allowlist = req.app_data::<web::Data<Config>>().map_or(&[][..], |config| config.genre_allowlist.as_slice());
+    Ok(validate_book(version, genre_allowlist, new_book)?)
+}
+
+// Field rules of a book under the given schema version
+fn validate_book(version: SchemaVersion, genre_allowlist: &[String], new_book: &NewBook) -> Result<(), ApiError> {
+    if let Some(genre) = &new_book.genre {
+        check_genre(genre_allowlist, genre)?;
+    }
+    if let Some(language) = new_book.language.as_deref().filter(|language| !is_language_tag(language)) {
+        return Err(ApiError::InvalidLanguage(language.to_string()));
+    }
+    if let Some(kind) = new_book.kind.as_deref().filter(|kind| BookKind::parse(kind).is_none()) {
+        return Err(ApiError::InvalidKind(kind.to_string()));
+    }
+    version.validate(new_book).map_err(ApiError::Validation)
+}
+
+// 422 for a genre outside GENRE_ALLOWLIST; any genre passes while the list is empty
+fn check_genre(genre_allowlist: &[String], genre: &str) -> Result<(), ApiError> {
+    if genre_allowlist.is_empty() || genre_allowlist.iter().any(|allowed| allowed.eq_ignore_ascii_case(genre.trim())) {
+        return Ok(());
+    }
+    Err(ApiError::Validation(format!("genre {:?} is not one of: {}", genre, genre_allowlist.join(", "))))
+}
+
+// Build an RFC 8288 Link header for the pages around offset, keeping the other query parameters
+fn pagination_links(req: &HttpRequest, offset: usize, limit: usize, total: usize) -> String {
+    let params: Vec<&str> = req
+        .query_string()
+        .split('&')
+        .filter(|param| !param.is_empty() && !param.starts_with("limit=") && !param.starts_with("offset="))
+        .collect();
+    let link = |offset: usize, rel: &str| {
+        let mut query = params.clone();
+        let page = format!("limit={}&offset={}", limit, offset);
+        query.push(&page);
+        format!("<{}?{}>; rel=\"{}\"", req.path(), query.join("&"), rel)
+    };
+    let last = total.saturating_sub(1) / limit * limit;
+    let mut links = vec![link(0, "first")];
+    if offset > 0 {
+        links.push(link(offset.saturating_sub(limit), "prev"));
+    }
+    if offset + limit < total {
+        links.push(link(offset + limit, "next"));
+    }
+    links.push(link(last, "last"));
+    links.join(", ")
+}
+
+// Parse a "Range: items=0-49" header into a start and optional inclusive end;
+// malformed or non-items ranges are ignored and the full list is returned
+fn parse_items_range(req: &HttpRequest) -> Option<(usize, Option<usize>)> {
+    let value = req.headers().get(header::RANGE)?.to_str().ok()?;
+    let (start, end) = value.trim().strip_prefix("items=")?.split_once('-')?;
+    let start = start.trim().parse().ok()?;
+    let end = match end.trim() {
+        "" => None,
+        end => Some(end.parse().ok()?),
+    };
+    Some((start, end))
+}
+
+// Endpoint to get all books
+pub async fn get_books(req: HttpRequest, ctx: AuthContext, query: web::Query<ListQuery>, config: web::Data<Config>, books: web::Data<Books>) -> impl Responder {
+            info!("get all books");
+    if let Err(e) = check_query_params(&req, &LIST_QUERY_PARAMS) {
+        return e.error_response();
+    }
+    let sort = match parse_sort(query.sort.as_deref().unwrap_or(&config.default_sort)) {
+        Ok(sort) => sort,
+        Err(message) => return ApiError::InvalidParameter(message).error_response(),
+    };
+    if let Some(language) = query.language.as_deref().filter(|language| !is_language_tag(language)) {
+        return ApiError::InvalidLanguage(language.to_string()).error_response();
+    }
+    let key = ListFlightKey::new(&query, &ctx);
+    let load = load_books(&req, &query, sort, &ctx, &config, &books, &key);
+    let books = match req.app_data::<web::Data<ListFlights>>() {
+        Some(flights) => flights.run(key.clone(), load).await,
+        None => load.await,
+    };
+    let loaded = match books {
+        Ok(loaded) => loaded,
+        Err(e) => return ApiError::from(e).error_response(),
+    };
+    let (books, truncated) = (&loaded.books, loaded.truncated);
+    let total = books.len();
+    // Prefer: handling=lenient clamps a limit outside 1..=MAX_LIST_LIMIT; strict handling, the default, rejects it
+    let lenient = preference(&req, "handling").as_deref() == Some("lenient");
+    let limit = match query.limit {
+        Some(limit) if lenient => Some(limit.clamp(1, MAX_LIST_LIMIT)),
+        Some(0) => return ApiError::InvalidParameter("limit must be positive".to_string()).error_response(),
+        Some(limit) if limit > MAX_LIST_LIMIT => return ApiError::InvalidParameter(format!("limit must be at most {}", MAX_LIST_LIMIT)).error_response(),
+        limit => limit,
+    };
+    // Explicit limit/offset pagination takes precedence over a Range header
+    let paginated = limit.is_some() || query.offset.is_some();
+    let range = if paginated { None } else { parse_items_range(&req) };
+    let (mut response, books) = match range {
+        None => {
+            let offset = query.offset.unwrap_or(0);
+            let start = offset.min(total);
+            let end = limit.map_or(total, |limit| start.saturating_add(limit).min(total));
+            let mut response = HttpResponse::Ok();
+            if let Some(limit) = limit {
+                response.insert_header((header::LINK, pagination_links(&req, offset, limit, total)));
+            }
+            (response, &books[start..end])
+        }
+        Some((start, end)) => {
+            if start >= total || end.is_some_and(|end| end < start) {
+                return HttpResponse::RangeNotSatisfiable()
+                    .insert_header((header::CONTENT_RANGE, format!("items */{}", total)))
+                    .finish();
+            }
+            let end = end.map_or(total - 1, |end| end.min(total - 1));
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((header::CONTENT_RANGE, format!("items {}-{}/{}", start, end, total)));
+            (response, &books[start..=end])
+        }
+    };
+    response.insert_header((header::ACCEPT_RANGES, "items"));
+    if lenient {
+        response.insert_header(("Preference-Applied", "handling=lenient"));
+    }
+    if truncated {
+        response.insert_header((RESULTS_TRUNCATED_HEADER, "true"));
+    }
+    let id_as_string = query.id_as_string.unwrap_or(false);
+    let body = debug_span!("get_books.serialize").in_scope(|| {
+        if query.envelope.unwrap_or(false) {
+            let pagination = Pagination::new(total, limit, query.offset.unwrap_or(0));
+            to_json_body(&Envelope { data: books, pagination, truncated }, id_as_string, config.id_prefix.as_deref())
+        } else {
+            to_json_body(&books, id_as_string, config.id_prefix.as_deref())
+        }
+    });
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => return ApiError::Internal(e.to_string()).error_response(),
+    };
+    // Weak: the tag is a hash of the content, not a promise about the exact bytes on the wire
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = entity_tag(&format!("{:016x}", hasher.finish()), true);
+    if if_none_match(&req, &etag) {
+        return HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish();
+    }
+    response.insert_header((header::ETAG, etag)).content_type(ContentType::json()).body(body)
+}
+
+// Endpoint to download the books matching the list filters as a JSON file, without pagination
+pub async fn export_books_json(req: HttpRequest, ctx: AuthContext, query: web::Query<ListQuery>, config: web::Data<Config>, books: web::Data<Books>) -> impl Responder {
+    info!("export books as json");
+    let sort = match parse_sort(query.sort.as_deref().unwrap_or(&config.default_sort)) {
+        Ok(sort) => sort,
+        Err(message) => return ApiError::InvalidParameter(message).error_response(),
+    };
+    if let Some(language) = query.language.as_deref().filter(|language| !is_language_tag(language)) {
+        return ApiError::InvalidLanguage(language.to_string()).error_response();
+    }
+    let key = ListFlightKey::new(&query, &ctx);
+    match load_books(&req, &query, sort, &ctx, &config, &books, &key).await {
+        Ok(loaded) => json_with_ids(&req, HttpResponse::Ok().insert_header((header::CONTENT_DISPOSITION, "attachment; filename=\"books.json\"")), &loaded.books),
+        Err(e) => ApiError::from(e).error_response(),
+    }
+}
+
+// Read, filter and sort the books a list request asks for, reusing the cached result while the store
+// is unchanged. Within LIST_CACHE_SWR_SECS of a change the outdated result is still served, and the
+// first request to see it recomputes it in the background
+async fn load_books(req: &HttpRequest, query: &ListQuery, sort: Vec<(SortKey, SortDirection)>, ctx: &AuthContext, config: &Config, books: &Books, key: &ListFlightKey) -> Result<Arc<LoadedBooks>, StoreError> {
+    // Debug-level spans time each phase when a tracing subscriber is installed
+    let store = read_store(req, books).instrument(debug_span!("get_books.lock")).await?;
+    let cache = req.app_data::<web::Data<ListCache>>();
+    if let Some(cache) = cache {
+        match cache.lookup(key, store.revision(), Duration::from_secs(config.list_cache_swr_secs)) {
+            Some((loaded, Freshness::Fresh)) => return Ok(loaded),
+            Some((loaded, Freshness::Stale { refresh })) => {
+                if refresh {
+                    let (cache, books, query, ctx, config, key) = (cache.clone(), books.clone(), query.clone(), ctx.clone(), config.clone(), key.clone());
+                    actix_web::rt::spawn(async move {
+                        let store = books.read().await;
+                        let loaded = Arc::new(filter_books(&store, &query, &sort, &ctx, &config));
+                        cache.insert(key, store.revision(), loaded);
+                    });
+                }
+                return Ok(loaded);
+            }
+            None => {}
+        }
+    }
+    let loaded = Arc::new(filter_books(&store, query, &sort, ctx, config));
+    if let Some(cache) = cache {
+        cache.insert(key.clone(), store.revision(), loaded.clone());
+    }
+    Ok(loaded)
+}
+
+// The books of the store a list request matches, in order and cut at MAX_SEARCH_RESULTS
+fn filter_books(store: &Store, query: &ListQuery, sort: &[(SortKey, SortDirection)], ctx: &AuthContext, config: &Config) -> LoadedBooks {
+    let mut books: Vec<Book> = debug_span!("get_books.filter").in_scope(|| {
+        let mut ranked: Vec<(MatchRank, &Book)> = match &query.q {
+            Some(q) => {
+                let fuzzy = query.fuzzy.unwrap_or(false);
+                // Fuzzy matches can't be looked up by token, so they still scan every book
+                let candidates = if fuzzy { store.books.iter().collect() } else { store.search_candidates(q) };
+                candidates
+                    .into_iter()
+                    .filter_map(|b| match_book(b, q, fuzzy, config.fuzzy_max_distance).map(|rank| (rank, b)))
+                    .collect()
+            }
+            None => store.books.iter().map(|b| (MatchRank::Exact, b)).collect(),
+        };
+        ranked.retain(|(_, book)| {
+            ctx.can_see(book)
+                && query.language.as_deref().is_none_or(|language| language_matches(book.language.as_deref(), language))
+                && query.tag.as_deref().is_none_or(|tag| book.tags.iter().any(|t| t.to_lowercase() == tag.trim().to_lowercase()))
+        });
+        // An explicit sort wins over relevance; the configured default only orders books within a rank.
+        // Remaining ties go by id, so equal keys come back in the same order whatever the store order
+        if query.sort.is_some() {
+            ranked.sort_by(|(rank_a, a), (rank_b, b)| compare_books(a, b, sort).then(rank_a.cmp(rank_b)).then(a.id.cmp(&b.id)));
+        } else {
+            ranked.sort_by(|(rank_a, a), (rank_b, b)| rank_a.cmp(rank_b).then_with(|| compare_books(a, b, sort)).then(a.id.cmp(&b.id)));
+        }
+        ranked.into_iter().map(|(_, b)| b.clone()).collect()
+    });
+    let truncated = query.q.is_some() && books.len() > config.max_search_results;
+    if truncated {
+        books.truncate(config.max_search_results);
+    }
+    LoadedBooks { books, truncated }
+}
+
+// Parameters understood by the ids endpoint: the list filters and sort, without pagination or envelopes
+const IDS_QUERY_PARAMS: [&str; 7] = ["q", "fuzzy", "sort", "id_as_string", "language", "tag", "strict"];
+
+// Endpoint to list just the ids of the books the same filters would return, in list order
+pub async fn get_book_ids(req: HttpRequest, ctx: AuthContext, query: web::Query<ListQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get book ids");
+    check_query_params(&req, &IDS_QUERY_PARAMS)?;
+    let defaults = Config::default();
+    let config = req.app_data::<web::Data<Config>>().map_or(&defaults, |c| c.get_ref());
+    let sort = parse_sort(query.sort.as_deref().unwrap_or(&config.default_sort)).map_err(ApiError::InvalidParameter)?;
+    if let Some(language) = query.language.as_deref().filter(|language| !is_language_tag(language)) {
+        return Err(ApiError::InvalidLanguage(language.to_string()));
+    }
+    let store = read_store(&req, &books).await?;
+    let loaded = filter_books(&store, &query, &sort, &ctx, config);
+    drop(store);
+    let prefix = id_prefix(&req);
+    let ids: Vec<serde_json::Value> = loaded
+        .books
+        .iter()
+        .map(|b| match prefix {
+            Some(_) => serde_json::Value::String(external_id(b.id.into(), prefix)),
+            None if query.id_as_string.unwrap_or(false) => serde_json::Value::String(b.id.to_string()),
+            None => b.id.into(),
+        })
+        .collect();
+    let mut response = HttpResponse::Ok();
+    response.insert_header((TOTAL_COUNT_HEADER, ids.len().to_string()));
+    if loaded.truncated {
+        response.insert_header((RESULTS_TRUNCATED_HEADER, "true"));
+    }
+    Ok(response.json(ids))
+}
+
+// Endpoint to get the most recently updated books
+pub async fn get_recent_books(req: HttpRequest, ctx: AuthContext, query: web::Query<RecentQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get recent books");
+    let limit = query.limit.unwrap_or(DEFAULT_RECENT_LIMIT).min(MAX_RECENT_LIMIT);
+    let store = read_store(&req, &books).await?;
+    let mut recent: Vec<Book> = store.books.iter().filter(|b| ctx.can_see(b)).cloned().collect();
+    recent.sort_by_key(|b| (std::cmp::Reverse(b.updated_at), b.id));
+    recent.truncate(limit);
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &recent))
+}
+
+// Endpoint to get one visible book picked uniformly at random, using the registered Rng
+pub async fn get_random_book(req: HttpRequest, ctx: AuthContext, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get random book");
+    let store = read_store(&req, &books).await?;
+    let visible: Vec<&Book> = store.books.iter().filter(|b| ctx.can_see(b)).collect();
+    if visible.is_empty() {
+        return Err(ApiError::NotFound("no books to pick from".to_string()));
+    }
+    let index = match req.app_data::<web::Data<Rng>>() {
+        Some(rng) => rng.below(visible.len()),
+        None => Rng::new(None).below(visible.len()),
+    };
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &visible[index]))
+}
+
+// Endpoint to list the books with the longest titles, counted in Unicode scalar values
+pub async fn get_longest_titles(req: HttpRequest, ctx: AuthContext, query: web::Query<LongestTitlesQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get longest titles");
+    let limit = query.limit.unwrap_or(DEFAULT_LONGEST_TITLES_LIMIT).min(MAX_LONGEST_TITLES_LIMIT);
+    let store = read_store(&req, &books).await?;
+    let mut longest: Vec<&Book> = store.books.iter().filter(|b| ctx.can_see(b)).collect();
+    // Equally long titles go by id
+    longest.sort_by_key(|b| (std::cmp::Reverse(b.title.chars().count()), b.id));
+    longest.truncate(limit);
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &longest))
+}
+
+// Endpoint to page through the books whose surname (or title, with ?by=title) starts with a letter,
+// ordered by that field; Link headers point at the neighbouring pages
+pub async fn browse_books(req: HttpRequest, ctx: AuthContext, query: web::Query<BrowseQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("browse books");
+    let mut letters = query.letter.trim().chars();
+    let letter = match (letters.next(), letters.next()) {
+        (Some(letter), None) => letter.to_uppercase().next().unwrap_or(letter),
+        _ => return Err(ApiError::InvalidParameter("letter must be a single character".to_string())),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_BROWSE_LIMIT).min(MAX_BROWSE_LIMIT);
+    if limit == 0 {
+        return Err(ApiError::InvalidParameter("limit must be positive".to_string()));
+    }
+    let store = read_store(&req, &books).await?;
+    let sort_key = match query.by {
+        BrowseBy::Surname => |b: &Book| (normalize(b.author.split_whitespace().last().unwrap_or("")), normalize(&b.title), b.id),
+        BrowseBy::Title => |b: &Book| (normalize(&b.title), normalize(&b.author), b.id),
+    };
+    let mut matching: Vec<&Book> = store
+        .books
+        .iter()
+        .filter(|b| ctx.can_see(b) && query.by.initial(b) == Some(letter))
+        .collect();
+    matching.sort_by_cached_key(|b| sort_key(b));
+    let total = matching.len();
+    let offset = query.offset.unwrap_or(0);
+    let start = offset.min(total);
+    let end = start.saturating_add(limit).min(total);
+    Ok(json_with_ids(&req, HttpResponse::Ok().insert_header((header::LINK, pagination_links(&req, offset, limit, total))), &&matching[start..end]))
+}
+
+// Endpoint to list the initials that have at least one book, in alphabetical order
+pub async fn browse_index(req: HttpRequest, ctx: AuthContext, query: web::Query<BrowseIndexQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("browse index");
+    let store = read_store(&req, &books).await?;
+    let initials: BTreeSet<String> = store
+        .books
+        .iter()
+        .filter(|b| ctx.can_see(b))
+        .filter_map(|b| query.by.initial(b))
+        .map(String::from)
+        .collect();
+    Ok(HttpResponse::Ok().json(initials))
+}
+
+// Endpoint to suggest distinct author names starting with a prefix
+pub async fn suggest_authors(req: HttpRequest, ctx: AuthContext, query: web::Query<SuggestQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("suggest authors");
+    let prefix = query.prefix.as_deref().unwrap_or("").to_lowercase();
+    let store = read_store(&req, &books).await?;
+    // Keyed by the lowercased name so case variants collapse to the first one seen
+    let mut authors: Vec<(String, String)> = vec![];
+    for book in store.books.iter().filter(|b| ctx.can_see(b)) {
+        let key = book.author.to_lowercase();
+        if key.starts_with(&prefix) && !authors.iter().any(|(k, _)| *k == key) {
+            authors.push((key, book.author.clone()));
+        }
+    }
+    authors.sort();
+    let suggestions: Vec<String> = authors.into_iter().take(MAX_AUTHOR_SUGGESTIONS).map(|(_, name)| name).collect();
+    Ok(HttpResponse::Ok().json(suggestions))
+}
+
+// Endpoint to list books lacking any of the required recommended fields, in store order
+pub async fn get_incomplete_books(req: HttpRequest, ctx: AuthContext, query: web::Query<IncompleteQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get incomplete books");
+    let required: Vec<&str> = match query.require.as_deref() {
+        Some(require) => require.split(',').map(str::trim).filter(|field| !field.is_empty()).collect(),
+        None => COMPLETENESS_FIELDS.to_vec(),
+    };
+    if let Some(field) = required.iter().find(|field| !COMPLETENESS_FIELDS.contains(field)) {
+        return Err(ApiError::InvalidParameter(format!("unknown field {:?}, expected one of {}", field, COMPLETENESS_FIELDS.join(", "))));
+    }
+    let missing = |book: &Book, field: &str| match field {
+        "isbn" => book.isbn.is_none(),
+        "genre" => book.genre.is_none(),
+        _ => book.published_year.is_none(),
+    };
+    let store = read_store(&req, &books).await?;
+    let incomplete: Vec<&Book> = store
+        .books
+        .iter()
+        .filter(|b| ctx.can_see(b) && required.iter().any(|field| missing(b, field)))
+        .collect();
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &incomplete))
+}
+
+// Endpoint to count books per author, most prolific first and ties by name
+pub async fn count_books_by_author(req: HttpRequest, ctx: AuthContext, query: web::Query<CountByAuthorQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("count books by author");
+    let store = read_store(&req, &books).await?;
+    // Keyed by the lowercased name so case variants count together under the first one seen
+    let mut counts: HashMap<String, AuthorCount> = HashMap::new();
+    for book in store.books.iter().filter(|b| ctx.can_see(b)) {
+        counts
+            .entry(book.author.to_lowercase())
+            .or_insert_with(|| AuthorCount {
+                author: book.author.clone(),
+                count: 0,
+            })
+            .count += 1;
+    }
+    let mut counts: Vec<(String, AuthorCount)> = counts.into_iter().collect();
+    counts.sort_by(|(key_a, a), (key_b, b)| b.count.cmp(&a.count).then_with(|| key_a.cmp(key_b)));
+    let counts: Vec<AuthorCount> = counts.into_iter().take(query.limit.unwrap_or(usize::MAX)).map(|(_, count)| count).collect();
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+// Endpoint to count books per decade of publication, oldest first; books without a year are left out
+pub async fn count_books_by_decade(req: HttpRequest, ctx: AuthContext, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("count books by decade");
+    let store = read_store(&req, &books).await?;
+    let mut counts: BTreeMap<i32, usize> = BTreeMap::new();
+    for year in store.books.iter().filter(|b| ctx.can_see(b)).filter_map(|b| b.published_year) {
+        *counts.entry(year.div_euclid(10) * 10).or_default() += 1;
+    }
+    let counts: Vec<DecadeCount> = counts.into_iter().map(|(decade, count)| DecadeCount { decade, count }).collect();
+    Ok(HttpResponse::Ok().json(counts))
+}
+
+// Header carrying the number of books on derived endpoints, so HEAD probes stay useful
+pub const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
+
+// Endpoint to count the books in the collection
+pub async fn count_books(req: HttpRequest, ctx: AuthContext, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("count books");
+    let store = read_store(&req, &books).await?;
+    let count = store.books.iter().filter(|b| ctx.can_see(b)).count();
+    Ok(HttpResponse::Ok()
+        .insert_header((TOTAL_COUNT_HEADER, count.to_string()))
+        .json(serde_json::json!({ "count": count })))
+}
+
+// Endpoint to summarize the collection
+pub async fn get_books_stats(req: HttpRequest, ctx: AuthContext, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get books stats");
+    let store = read_store(&req, &books).await?;
+    let visible: Vec<&Book> = store.books.iter().filter(|b| ctx.can_see(b)).collect();
+    let mut authors: Vec<String> = visible.iter().map(|b| b.author.to_lowercase()).collect();
+    authors.sort();
+    authors.dedup();
+    let stats = BookStats {
+        total_books: visible.len(),
+        distinct_authors: authors.len(),
+        last_updated_at: visible.iter().map(|b| b.updated_at).max(),
+    };
+    // Every write moves one of these, and equal values mean an equivalent body
+    let etag = entity_tag(&format!("{}-{}-{}", stats.total_books, stats.distinct_authors, stats.last_updated_at.unwrap_or(0)), true);
+    if if_none_match(&req, &etag) {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag))
+        .insert_header((TOTAL_COUNT_HEADER, stats.total_books.to_string()))
+        .json(stats))
+}
+
+// Endpoint to describe the book models as JSON Schema
+pub async fn get_books_schema() -> impl Responder {
+    info!("get books schema");
+    HttpResponse::Ok().json(serde_json::json!({
+        "book": schema_for!(Book),
+        "new_book": schema_for!(NewBook),
+    }))
+}
+
+// Endpoint to check which title and author pairs already exist, answering in request order
+pub async fn books_exist(req: HttpRequest, ctx: AuthContext, pairs: web::Json<Vec<NewBook>>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("check {} books exist", pairs.len());
+    let store = read_store(&req, &books).await?;
+    let results: Vec<ExistsResult> = pairs
+        .iter()
+        .map(|pair| {
+            let id = store.matching(&pair.title, &pair.author).filter(|b| ctx.can_see(b)).map(|b| b.id).min();
+            ExistsResult { exists: id.is_some(), id }
+        })
+        .collect();
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &results))
+}
+
+// Endpoint to list groups of duplicate books, as a dedupe would merge them
+pub async fn get_duplicate_books(req: HttpRequest, ctx: AuthContext, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get duplicate books");
+    let store = read_store(&req, &books).await?;
+    // Groups never span owners or tenants, so the first book decides visibility for all
+    let groups: Vec<DuplicateGroup> = store
+        .duplicate_groups()
+        .into_iter()
+        .filter_map(|ids| {
+            let first = ctx.find(&store, ids[0])?;
+            Some(DuplicateGroup {
+                title: first.title.clone(),
+                author: first.author.clone(),
+                ids,
+            })
+        })
+        .collect();
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &groups))
+}
+
+// Endpoint to find a book by its normalized title and author
+pub async fn lookup_book(req: HttpRequest, ctx: AuthContext, query: web::Query<LookupQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("lookup book");
+    check_query_params(&req, &["title", "author", "strict"])?;
+    let store = read_store(&req, &books).await?;
+    let matches: Vec<&Book> = store.matching(&query.title, &query.author).filter(|b| ctx.can_see(b)).collect();
+    match matches[..] {
+        [] => Err(StoreError::NotFound.into()),
+        [book] => Ok(json_with_ids(&req, &mut HttpResponse::Ok(), book)),
+        _ => Err(ApiError::Ambiguous(matches.iter().map(|b| b.id).collect())),
+    }
+}
+
+// Endpoint to get a book by id
+pub async fn get_book(req: HttpRequest, ctx: AuthContext, id: BookId, query: web::Query<IdFormatQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+        info!("get book");
+    let mut author_books = false;
+    for include in query.include.iter().flat_map(|include| include.split(',')).map(str::trim).filter(|include| !include.is_empty()) {
+        match include {
+            "author_books" => author_books = true,
+            other => return Err(ApiError::InvalidParameter(format!("unknown include {:?}, expected \"author_books\"", other))),
+        }
+    }
+    // Embedding author books needs the store anyway, so only plain reads go through the cache
+    let cache = req.app_data::<web::Data<BookCache>>().filter(|_| !author_books);
+    let (book, store) = match cache.and_then(|cache| cache.get(&id)).filter(|book| ctx.can_see(book)) {
+        Some(book) => (book, None),
+        None => {
+            let store = read_store(&req, &books).await?;
+            let book = ctx.find(&store, *id).ok_or(StoreError::NotFound)?.clone();
+            if let Some(cache) = cache {
+                cache.insert(book.id, book.clone());
+            }
+            (book, Some(store))
+        }
+    };
+    let book = &book;
+    let id_as_string = query.id_as_string.unwrap_or(false);
+    let hateoas = query.hateoas.unwrap_or(false);
+    let limit = query.author_books_limit.unwrap_or(DEFAULT_AUTHOR_BOOKS_LIMIT).min(MAX_AUTHOR_BOOKS_LIMIT);
+    // Strong: each version of a book renders to exactly one body per id format and link mode.
+    // Embedded books can change without the book itself, so then the store revision counts too
+    let embedded = match &store {
+        Some(store) if author_books => format!("-authors{}-{}", limit, store.revision()),
+        _ => String::new(),
+    };
+    let etag = entity_tag(&format!("{}{}{}{}", book.version, if id_as_string { "-ids" } else { "" }, if hateoas { "-links" } else { "" }, embedded), false);
+    if if_none_match(&req, &etag) {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+    }
+    let body = serde_json::to_value(book).and_then(|mut body| {
+        if hateoas {
+            body["_links"] = book_links(book.id, id_prefix(&req));
+        }
+        if let Some(store) = store.as_ref().filter(|_| author_books) {
+            let author = book.author.to_lowercase();
+            let others: Vec<&Book> = store
+                .books
+                .iter()
+                .filter(|b| b.id != book.id && b.author.to_lowercase() == author && ctx.can_see(b))
+                .take(limit)
+                .collect();
+            body["author_books"] = serde_json::to_value(others)?;
+        }
+        to_json_body(&body, id_as_string, id_prefix(&req))
+    });
+    match body {
+        Ok(body) => Ok(HttpResponse::Ok().insert_header((header::ETAG, etag)).content_type(ContentType::json()).body(body)),
+        Err(e) => Err(ApiError::Internal(e.to_string())),
+    }
+}
+
+// Endpoint to create a new book
+pub async fn create_book(req: HttpRequest, ctx: AuthContext, new_book: web::Json<NewBook>, books: web::Data<Books>) -> impl Responder {
+    info!("create book");
+    if let Err(e) = validate_payload(&req, &new_book) {
+        return e.error_response();
+    }
+    let owner = ctx.principal.map(|principal| principal.name);
+    let tenant_id = ctx.tenant.map(|tenant| tenant.0);
+    let mut store = books.write().await;
+    if let Err(e) = check_quota(&store, max_books(&req), tenant_id.as_deref(), 1) {
+        return e.error_response();
+    }
+    let book = store.insert(&new_book, owner, tenant_id);
+    drop(store);
+    let mut response = HttpResponse::Created();
+    response.insert_header((header::LOCATION, format!("/books/{}", external_id(book.id.into(), id_prefix(&req)))));
+    if preference(&req, "return").as_deref() == Some("minimal") {
+        return response.insert_header(("Preference-Applied", "return=minimal")).finish();
+    }
+    match to_json_body(&book, false, id_prefix(&req)) {
+        Ok(body) => response.content_type(ContentType::json()).body(body),
+        Err(e) => ApiError::Internal(e.to_string()).error_response(),
+    }
+}
+
+// Header carrying how many books a bulk create added
+pub const CREATED_COUNT_HEADER: &str = "X-Created-Count";
+
+// Endpoint to create many books at once; nothing is created unless every payload is valid
+pub async fn bulk_create_books(req: HttpRequest, ctx: AuthContext, new_books: web::Json<Vec<NewBook>>, books: web::Data<Books>) -> impl Responder {
+    info!("bulk create books");
+    for new_book in new_books.iter() {
+        if let Err(e) = validate_payload(&req, new_book) {
+            return e.error_response();
+        }
+    }
+    let owner = ctx.principal.map(|principal| principal.name);
+    let tenant_id = ctx.tenant.map(|tenant| tenant.0);
+    let mut store = books.write().await;
+    // All or nothing: a batch that doesn't fit creates no book at all
+    if let Err(e) = check_quota(&store, max_books(&req), tenant_id.as_deref(), new_books.len()) {
+        return e.error_response();
+    }
+    let created: Vec<Book> = new_books
+        .iter()
+        .map(|new_book| store.insert(new_book, owner.clone(), tenant_id.clone()))
+        .collect();
+    json_with_ids(&req, HttpResponse::Created().insert_header((CREATED_COUNT_HEADER, created.len().to_string())), &created)
+}
+
+// The configured MAX_BOOKS, if any
+fn max_books(req: &HttpRequest) -> Option<usize> {
+    req.app_data::<web::Data<Config>>().and_then(|config| config.max_books)
+}
+
+// 403 when adding books would take the tenant (or, for books without one, the store) past MAX_BOOKS
+fn check_quota(store: &Store, max_books: Option<usize>, tenant_id: Option<&str>, adding: usize) -> Result<(), ApiError> {
+    let Some(max_books) = max_books else {
+        return Ok(());
+    };
+    let held = store.books.iter().filter(|b| b.tenant_id.as_deref() == tenant_id).count();
+    if held.saturating_add(adding) <= max_books {
+        return Ok(());
+    }
+    Err(ApiError::QuotaExceeded(format!(
+        "the collection is limited to {} books and holds {}; {} more cannot be added",
+        max_books, held, adding
+    )))
+}
+
+// Endpoint to check ISBNs ahead of an import, one result per submitted string in the same order
+pub async fn validate_isbns(isbns: web::Json<Vec<String>>) -> impl Responder {
+    info!("validate isbns");
+    let checks: Vec<IsbnCheck> = isbns
+        .into_inner()
+        .into_iter()
+        .map(|isbn| {
+            let normalized = normalize_isbn(&isbn);
+            IsbnCheck {
+                isbn,
+                valid: normalized.is_some(),
+                normalized,
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(checks)
+}
+
+// Endpoint to import books with their ids, e.g. from GET /books/export.json. A taken id is handled
+// by ?on_conflict; nothing is imported unless every book is valid and, for "error", no id is taken.
+// With ?async=true the books are checked up front, then imported by a background job whose
+// progress is polled through the Location of the 202 response
+pub async fn import_books(req: HttpRequest, ctx: AuthContext, query: web::Query<ImportQuery>, imported: web::Json<Vec<Book>>, books: web::Data<Books>) -> impl Responder {
+    info!("import books");
+    for book in imported.iter() {
+        if let Err(e) = validate_payload(&req, &NewBook::from(book)) {
+            return e.error_response();
+        }
+    }
+    let imported = imported.into_inner();
+    if query.run_async {
+        let Some(jobs) = req.app_data::<web::Data<Jobs>>().cloned() else {
+            return ApiError::Internal("no job registry registered".to_string()).error_response();
+        };
+        let job = jobs.start(imported.len());
+        let (id, books, policy, max_books) = (job.id, books.clone(), query.on_conflict, max_books(&req));
+        let cache = req.app_data::<web::Data<BookCache>>().cloned();
+        actix_web::rt::spawn(async move {
+            jobs.set_running(id);
+            let mut store = books.write().await;
+            let result = import_into(&mut store, &ctx, policy, max_books, imported);
+            if let Some(cache) = cache {
+                cache.clear();
+            }
+            drop(store);
+            jobs.finish(id, result.map_err(|e| e.to_string()));
+        });
+        return HttpResponse::Accepted().insert_header((header::LOCATION, format!("/admin/jobs/{}", id))).json(job);
+    }
+    let mut store = books.write().await;
+    let result = import_into(&mut store, &ctx, query.on_conflict, max_books(&req), imported);
+    // Overwrites may have replaced any book
+    forget_all_books(&req);
+    drop(store);
+    match result {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => e.error_response(),
+    }
+}
+
+// Apply validated imported books to the store under the given conflict policy
+fn import_into(store: &mut Store, ctx: &AuthContext, policy: ConflictPolicy, max_books: Option<usize>, imported: Vec<Book>) -> Result<ImportReport, ApiError> {
+    // Ids taken by the store or by an earlier book of the same import
+    let mut seen = HashSet::new();
+    let taken: Vec<i32> = imported
+        .iter()
+        .filter(|book| !seen.insert(book.id) || store.books.iter().any(|b| b.id == book.id))
+        .map(|book| book.id)
+        .collect();
+    // Books the caller can't see are never overwritten, whatever the policy
+    let hidden = taken.iter().any(|id| store.books.iter().any(|b| b.id == *id && !ctx.can_see(b)));
+    if !taken.is_empty() && (policy == ConflictPolicy::Error || (policy == ConflictPolicy::Overwrite && hidden)) {
+        let ids: Vec<String> = taken.iter().map(i32::to_string).collect();
+        return Err(ApiError::Conflict(format!("ids already exist: {}", ids.join(", "))));
+    }
+    let owner = ctx.principal.as_ref().filter(|principal| !principal.admin).map(|principal| principal.name.clone());
+    let tenant_id = ctx.tenant.as_ref().map(|tenant| tenant.0.clone());
+    // Overwritten and skipped books don't count against the quota, only newly created ones;
+    // an id repeated within the import creates one book at most
+    let adding = imported
+        .iter()
+        .map(|book| book.id)
+        .filter(|id| !store.books.iter().any(|b| b.id == *id))
+        .collect::<HashSet<_>>()
+        .len();
+    check_quota(store, max_books, tenant_id.as_deref(), adding)?;
+    let mut report = ImportReport::default();
+    for book in imported {
+        let exists = store.books.iter().any(|b| b.id == book.id);
+        if exists && policy == ConflictPolicy::Skip {
+            report.skipped += 1;
+        } else if exists {
+            // Owner and tenant stay with the stored book; the content is replaced and, like any
+            // update, stamped and versioned by the store
+            store.update(book.id, |stored| stored.set_content(book));
+            report.overwritten += 1;
+        } else {
+            // A non-admin caller or a tenant claims imported books the same way creation does, and
+            // the book starts out fresh whatever timestamp and version the payload carried
+            store.insert_with_id(Book {
+                owner: owner.clone().or(book.owner),
+                tenant_id: tenant_id.clone().or(book.tenant_id),
+                updated_at: now_millis(),
+                version: 1,
+                ..book
+            });
+            report.created += 1;
+        }
+    }
+    Ok(report)
+}
+
+// Endpoint to update a book
+pub async fn update_book(req: HttpRequest, ctx: AuthContext, id: BookId, query: web::Query<UpdateQuery>, new_book: web::Json<NewBook>, books: web::Data<Books>) -> impl Responder {
+    info!("update book");
+    if let Err(e) = validate_payload(&req, &new_book) {
+        return e.error_response();
+    }
+    let allow_duplicates = req.app_data::<web::Data<Config>>().is_some_and(|config| config.allow_duplicates);
+    let mut store = books.write().await;
+    let Some(current) = ctx.find(&store, *id) else {
+        return ApiError::from(StoreError::NotFound).error_response();
+    };
+    let renamed = Book {
+        title: new_book.title.clone(),
+        author: new_book.author.clone(),
+        ..current.clone()
+    };
+    if let Some(other) = store.duplicate_of(&renamed, &[*id]).filter(|_| !allow_duplicates) {
+        return duplicate_conflict(other).error_response();
+    }
+    let before = current.clone();
+    let book = store.update(*id, |book| {
+        book.title = new_book.title.clone();
+        book.author = new_book.author.clone();
+        book.language = new_book.language.clone();
+        book.kind = new_book.kind.as_deref().and_then(BookKind::parse).unwrap_or_default();
+        book.issue_number = new_book.issue_number;
+        book.isbn = new_book.isbn.clone();
+        book.published_year = new_book.published_year;
+        if let Some(genre) = &new_book.genre {
+            book.genre = Some(genre.clone());
+        }
+    });
+    forget_books(&req, [*id]);
+    drop(store);
+    match book {
+        Some(book) if query.return_mode == ReturnMode::Changed => match changed_fields(&before, &book) {
+            Ok(changed) => json_with_ids(&req, &mut HttpResponse::Ok(), &changed),
+            Err(e) => ApiError::Internal(e.to_string()).error_response(),
+        },
+        Some(book) => match to_json_body(&book, false, id_prefix(&req)) {
+            Ok(body) => HttpResponse::Ok().content_type(ContentType::json()).body(body),
+            Err(e) => ApiError::Internal(e.to_string()).error_response(),
+        },
+        None => ApiError::from(StoreError::NotFound).error_response(),
+    }
+}
+
+// 409 for a write that would give a book the natural key of another one
+fn duplicate_conflict(other: i32) -> ApiError {
+    ApiError::Conflict(format!("book {} already has this title and author", other))
+}
+
+// Endpoint to apply the same partial change to many books at once
+pub async fn batch_update_books(req: HttpRequest, ctx: AuthContext, query: web::Query<UpdateQuery>, batch: web::Json<BatchUpdate>, books: web::Data<Books>) -> impl Responder {
+    info!("batch update books");
+    let version = match SchemaVersion::from_request(&req) {
+        Ok(version) => version,
+        Err(message) => return ApiError::InvalidSchemaVersion(message).error_response(),
+    };
+    if let Err(message) = version.validate_changes(&batch.changes) {
+        return ApiError::Validation(message).error_response();
+    }
+    let allow_duplicates = req.app_data::<web::Data<Config>>().is_some_and(|config| config.allow_duplicates);
+    // Holding the write lock for the whole batch keeps the update atomic, and the transaction
+    // undoes the books already changed when a later one fails
+    let mut store = books.write().await;
+    let mut before: Vec<Book> = Vec::new();
+    let result = store.transaction(|store| {
+        let mut result = BatchUpdateResult {
+            updated: vec![],
+            missing: vec![],
+        };
+        for id in &batch.ids {
+            let updated = match ctx.find(store, *id) {
+                Some(book) => {
+                    before.push(book.clone());
+                    store.update(*id, |book| batch.changes.apply(book))
+                }
+                None => None,
+            };
+            match updated {
+                Some(book) => {
+                    if let Some(other) = store.duplicate_of(&book, &[book.id]).filter(|_| !allow_duplicates) {
+                        return Err(duplicate_conflict(other));
+                    }
+                    result.updated.push(book)
+                }
+                None => result.missing.push(*id),
+            }
+        }
+        Ok(result)
+    });
+    forget_books(&req, batch.ids.iter().copied());
+    drop(store);
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => return e.error_response(),
+    };
+    if query.return_mode == ReturnMode::Full {
+        return json_with_ids(&req, &mut HttpResponse::Ok(), &result);
+    }
+    // Listed ids may repeat, so each update is compared with the state just before it
+    let changed: serde_json::Result<Vec<serde_json::Value>> = before.iter().zip(&result.updated).map(|(before, after)| changed_fields(before, after)).collect();
+    match changed {
+        Ok(changed) => json_with_ids(&req, &mut HttpResponse::Ok(), &serde_json::json!({"updated": changed, "missing": result.missing})),
+        Err(e) => ApiError::Internal(e.to_string()).error_response(),
+    }
+}
+
+// Media type that routes PATCH /books to the batched JSON Patch endpoint
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+// Query parameters accepted by the batched JSON Patch endpoint
+#[derive(Deserialize)]
+pub struct BatchPatchQuery {
+    // Apply every patch or none of them
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+// Endpoint to apply a JSON Patch to each of many books. Every item succeeds or fails on its own;
+// with ?atomic=true the first failure rolls the batch back and the response takes its status
+pub async fn batch_patch_books(req: HttpRequest, ctx: AuthContext, query: web::Query<BatchPatchQuery>, patches: web::Json<Vec<BookPatch>>, books: web::Data<Books>) -> impl Responder {
+    info!("batch patch books");
+    let version = match SchemaVersion::from_request(&req) {
+        Ok(version) => version,
+        Err(message) => return ApiError::InvalidSchemaVersion(message).error_response(),
+    };
+    let defaults = Config::default();
+    let config = req.app_data::<web::Data<Config>>().map_or(&defaults, |config| config.get_ref());
+    let mut store = books.write().await;
+    let mut results: Vec<Result<Book, ApiError>> = Vec::new();
+    let mut patch_all = |store: &mut Store| {
+        for item in patches.iter() {
+            let result = patch_book(store, &ctx, version, config, item);
+            let failed = result.is_err();
+            results.push(result);
+            if failed && query.atomic {
+                return Err(());
+            }
+        }
+        Ok(())
+    };
+    let applied = if query.atomic { store.transaction(patch_all).is_ok() } else { patch_all(&mut store).is_ok() };
+    forget_books(&req, patches.iter().map(|item| item.id));
+    drop(store);
+    let mut status = actix_web::http::StatusCode::OK;
+    let mut results = results.into_iter();
+    let outcomes: Vec<PatchOutcome> = patches
+        .iter()
+        .map(|item| match results.next() {
+            Some(Ok(book)) if applied => PatchOutcome {
+                id: item.id,
+                status: 200,
+                book: Some(book),
+                error: None,
+            },
+            Some(Err(e)) => {
+                if query.atomic {
+                    status = e.status_code();
+                }
+                PatchOutcome {
+                    id: item.id,
+                    status: e.status_code().as_u16(),
+                    book: None,
+                    error: Some(ItemError { code: e.code().to_string(), message: e.to_string() }),
+                }
+            }
+            // Rolled back or never tried because another item of an atomic batch failed
+            _ => PatchOutcome {
+                id: item.id,
+                status: 424,
+                book: None,
+                error: Some(ItemError {
+                    code: "not_applied".to_string(),
+                    message: "another patch of the atomic batch failed".to_string(),
+                }),
+            },
+        })
+        .collect();
+    json_with_ids(&req, &mut HttpResponse::build(status), &serde_json::json!({ "results": outcomes }))
+}
+
+// Apply the JSON Patch of one batch item; the patched book has to pass the usual field rules
+// and keep its id, owner, tenant and bookkeeping fields
+fn patch_book(store: &mut Store, ctx: &AuthContext, version: SchemaVersion, config: &Config, item: &BookPatch) -> Result<Book, ApiError> {
+    let current = ctx.find(store, item.id).ok_or(StoreError::NotFound)?;
+    let document = serde_json::to_value(current).map_err(|e| ApiError::Internal(e.to_string()))?;
+    let patched = patch::apply(&document, &item.patch).map_err(ApiError::Validation)?;
+    let patched: Book = serde_json::from_value(patched).map_err(|e| ApiError::Validation(e.to_string()))?;
+    if (patched.id, patched.version, patched.updated_at, &patched.owner, &patched.tenant_id) != (current.id, current.version, current.updated_at, &current.owner, &current.tenant_id) {
+        return Err(ApiError::Validation("id, version, updated_at, owner and tenant_id cannot be patched".to_string()));
+    }
+    validate_book(version, &config.genre_allowlist, &NewBook::from(&patched))?;
+    if let Some(other) = store.duplicate_of(&patched, &[item.id]).filter(|_| !config.allow_duplicates) {
+        return Err(duplicate_conflict(other));
+    }
+    store.update(item.id, |book| book.set_content(patched)).ok_or_else(|| StoreError::NotFound.into())
+}
+
+// Endpoint to add and remove individual tags without resending the whole list
+pub async fn update_book_tags(req: HttpRequest, ctx: AuthContext, id: BookId, changes: web::Json<TagChanges>, books: web::Data<Books>) -> impl Responder {
+    info!("update book tags");
+    if changes.add.iter().chain(&changes.remove).any(|tag| tag.trim().is_empty()) {
+        return ApiError::Validation("tags must not be empty".to_string()).error_response();
+    }
+    let mut store = books.write().await;
+    if ctx.find(&store, *id).is_none() {
+        return ApiError::from(StoreError::NotFound).error_response();
+    }
+    let book = store.update(*id, |book| changes.apply(&mut book.tags));
+    forget_books(&req, [*id]);
+    drop(store);
+    match book {
+        Some(book) => json_with_ids(&req, &mut HttpResponse::Ok(), &book),
+        None => ApiError::from(StoreError::NotFound).error_response(),
+    }
+}
+
+// Query parameters accepted by the changes feed
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    // Milliseconds since the Unix epoch; only later changes are returned
+    pub since: u64,
+}
+
+// Endpoint for incremental sync: books created, updated or deleted after ?since, deletes as
+// tombstones. The ETag follows the store revision, so polling an unchanged store yields 304
+pub async fn get_book_changes(req: HttpRequest, ctx: AuthContext, query: web::Query<ChangesQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get book changes");
+    let store = read_store(&req, &books).await?;
+    let etag = entity_tag(&format!("{}-{}", store.revision(), query.since), true);
+    if if_none_match(&req, &etag) {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+    }
+    let mut updated: Vec<Book> = store.books.iter().filter(|b| b.updated_at > query.since && ctx.can_see(b)).cloned().collect();
+    updated.sort_by_key(|b| (b.updated_at, b.id));
+    let deleted = store
+        .deleted_since(query.since)
+        .into_iter()
+        .filter(|entry| ctx.can_see(&entry.book))
+        .map(|entry| Tombstone {
+            id: entry.book.id,
+            deleted_at: entry.timestamp,
+        })
+        .collect();
+    // Writes wait for the read lock, so later changes are stamped no earlier than now; stepping
+    // back a millisecond means one in this same millisecond is not missed, at worst sent twice
+    let until = now_millis().saturating_sub(1).max(query.since);
+    Ok(json_with_ids(&req, HttpResponse::Ok().insert_header((header::ETAG, etag)), &ChangesFeed { updated, deleted, until }))
+}
+
+// Endpoint to get the chronological change history of a book
+pub async fn get_book_history(req: HttpRequest, ctx: AuthContext, id: BookId, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get book history");
+    let store = read_store(&req, &books).await?;
+    // The latest snapshot decides visibility, so deleted books stay scoped too
+    let history = store
+        .history(*id)
+        .filter(|history| history.last().is_some_and(|entry| ctx.can_see(&entry.book)))
+        .ok_or(StoreError::NotFound)?;
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &history))
+}
+
+// Endpoint to recommend books by the same author, then books sharing the genre
+pub async fn get_similar_books(req: HttpRequest, ctx: AuthContext, id: BookId, query: web::Query<SimilarQuery>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get similar books");
+    let limit = query.limit.unwrap_or(DEFAULT_SIMILAR_LIMIT).min(MAX_SIMILAR_LIMIT);
+    let store = read_store(&req, &books).await?;
+    let base = ctx.find(&store, *id).ok_or(StoreError::NotFound)?;
+    let author = base.author.to_lowercase();
+    let genre = base.genre.as_deref().map(str::to_lowercase);
+    let others = || store.books.iter().filter(|b| b.id != base.id && ctx.can_see(b));
+    let same_author = others().filter(|b| b.author.to_lowercase() == author);
+    let same_genre = others().filter(|b| {
+        b.author.to_lowercase() != author && genre.is_some() && b.genre.as_deref().map(str::to_lowercase) == genre
+    });
+    let similar: Vec<&Book> = same_author.chain(same_genre).take(limit).collect();
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &similar))
+}
+
+// Endpoint to set the genre of every book matching a filter
+pub async fn tag_genre(req: HttpRequest, ctx: AuthContext, assignment: web::Json<GenreAssignment>, config: web::Data<Config>, books: web::Data<Books>) -> impl Responder {
+    info!("tag genre");
+    if assignment.filter.is_empty() {
+        return ApiError::Validation("filter must set at least one field".to_string()).error_response();
+    }
+    let genre = assignment.genre.trim();
+    if genre.is_empty() {
+        return ApiError::Validation("genre must not be empty".to_string()).error_response();
+    }
+    if let Err(e) = check_genre(&config.genre_allowlist, genre) {
+        return e.error_response();
+    }
+    let mut store = books.write().await;
+    let ids: Vec<i32> = store
+        .books
+        .iter()
+        .filter(|b| ctx.can_see(b) && filter_matches(&assignment.filter, b, &config))
+        .map(|b| b.id)
+        .collect();
+    for id in &ids {
+        store.update(*id, |book| book.genre = Some(genre.to_string()));
+    }
+    forget_books(&req, ids.iter().copied());
+    HttpResponse::Ok().json(serde_json::json!({ "updated": ids.len() }))
+}
+
+// Endpoint to reorder the reading list; listed books get positions 1..n and
+// every other book drops out of the list
+pub async fn reorder_books(req: HttpRequest, ctx: AuthContext, reorder: web::Json<Reorder>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("reorder books");
+    let mut store = books.write().await;
+    let mut seen = HashSet::new();
+    let mut duplicates: Vec<i32> = reorder.order.iter().copied().filter(|id| !seen.insert(*id)).collect();
+    if !duplicates.is_empty() {
+        duplicates.sort_unstable();
+        duplicates.dedup();
+        return Err(ApiError::DuplicateIds(duplicates));
+    }
+    let missing: Vec<i32> = reorder.order.iter().copied().filter(|id| ctx.find(&store, *id).is_none()).collect();
+    if !missing.is_empty() {
+        return Err(ApiError::UnknownIds(missing));
+    }
+    let dropped: Vec<i32> = store
+        .books
+        .iter()
+        .filter(|b| ctx.can_see(b) && b.position.is_some() && !seen.contains(&b.id))
+        .map(|b| b.id)
+        .collect();
+    for id in dropped {
+        store.update(id, |book| book.position = None);
+    }
+    let reordered: Vec<Book> = reorder
+        .order
+        .iter()
+        .enumerate()
+        .filter_map(|(index, id)| store.update(*id, |book| book.position = Some(index + 1)))
+        .collect();
+    // Dropped books lost their position too
+    forget_all_books(&req);
+    Ok(json_with_ids(&req, &mut HttpResponse::Ok(), &reordered))
+}
+
+// Image types accepted as covers, with the file extension each is stored under
+const COVER_TYPES: [(&str, &str); 2] = [("image/png", "png"), ("image/jpeg", "jpg")];
+
+// Endpoint to upload a book's cover image, replacing any previous one
+pub async fn put_book_cover(req: HttpRequest, ctx: AuthContext, id: BookId, payload: web::Payload, config: web::Data<Config>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("put book cover");
+    if ctx.find(&*books.read().await, *id).is_none() {
+        return Err(StoreError::NotFound.into());
+    }
+    let content_type = req.headers().get(header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or("");
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let Some((_, extension)) = COVER_TYPES.iter().find(|(cover_type, _)| *cover_type == mime) else {
+        return Err(ApiError::UnsupportedMediaType("cover must be image/png or image/jpeg".to_string()));
+    };
+    let image = match payload.to_bytes_limited(config.cover_max_bytes).await {
+        Ok(Ok(image)) => image,
+        Ok(Err(e)) => return Err(ApiError::InvalidBody(e.to_string())),
+        Err(_) => return Err(ApiError::PayloadTooLarge(format!("cover must be at most {} bytes", config.cover_max_bytes))),
+    };
+    let result = async {
+        tokio::fs::create_dir_all(&config.cover_dir).await?;
+        for (_, other) in COVER_TYPES.iter().filter(|(_, other)| other != extension) {
+            let _ = tokio::fs::remove_file(config.cover_dir.join(format!("{}.{}", *id, other))).await;
+        }
+        tokio::fs::write(config.cover_dir.join(format!("{}.{}", *id, extension)), &image).await
+    };
+    match result.await {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(e) => Err(ApiError::Internal(e.to_string())),
+    }
+}
+
+// Endpoint to download a book's cover image
+pub async fn get_book_cover(ctx: AuthContext, id: BookId, config: web::Data<Config>, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("get book cover");
+    if ctx.find(&*books.read().await, *id).is_none() {
+        return Err(StoreError::NotFound.into());
+    }
+    for (content_type, extension) in COVER_TYPES {
+        if let Ok(image) = tokio::fs::read(config.cover_dir.join(format!("{}.{}", *id, extension))).await {
+            return Ok(HttpResponse::Ok().content_type(content_type).body(image));
+        }
+    }
+    Err(ApiError::NotFound("cover not found".to_string()))
+}
+
+// Delete the cover files of books that were removed; a book without a cover is not an error
+async fn remove_covers(req: &HttpRequest, ids: impl IntoIterator<Item = i32>) {
+    let defaults = Config::default();
+    let config = req.app_data::<web::Data<Config>>().map_or(&defaults, |c| c.get_ref());
+    for id in ids {
+        for (_, extension) in COVER_TYPES {
+            let path = config.cover_dir.join(format!("{}.{}", id, extension));
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => warn!("failed to remove cover {}: {}", path.display(), e),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Format an entity tag; strong tags promise byte-identical bodies, weak (W/"...") ones only equivalent content
+fn entity_tag(opaque: &str, weak: bool) -> String {
+    format!("{}\"{}\"", if weak { "W/" } else { "" }, opaque)
+}
+
+// Check an If-Match header against a book version, using strong comparison as RFC 9110 requires
+// for If-Match, so weak tags never match; a missing header always matches
+fn if_match_version(req: &HttpRequest, version: u32) -> bool {
+    let Some(value) = req.headers().get(header::IF_MATCH) else {
+        return true;
+    };
+    let Ok(value) = value.to_str() else {
+        return false;
+    };
+    value.split(',').map(str::trim).any(|tag| {
+        // Every rendering of a version names the same state, so only the leading version counts
+        // and suffixes such as "-ids", "-links" or "-authors{limit}-{revision}" are ignored
+        tag == "*" || (!tag.starts_with("W/") && tag.trim_matches('"').split('-').next().and_then(|v| v.parse::<u32>().ok()) == Some(version))
+    })
+}
+
+// Check an If-None-Match header against an entity tag using weak comparison (RFC 9110 section 8.8.3.2),
+// where W/ is ignored on both sides; a missing header never matches
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    let Some(value) = req.headers().get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+    let opaque = etag.trim_start_matches("W/");
+    value
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == opaque)
+}
+
+// Endpoint to delete a book
+pub async fn delete_book(req: HttpRequest, ctx: AuthContext, id: BookId, books: web::Data<Books>) -> impl Responder {
+    info!("delete books");
+    let mut store = books.write().await;
+    let version = ctx.find(&store, *id).map(|b| b.version);
+    match version {
+        Some(version) => {
+            if !if_match_version(&req, version) {
+                return ApiError::PreconditionFailed("book version does not match If-Match".to_string()).error_response();
+            }
+            store.remove(*id);
+            forget_books(&req, [*id]);
+            drop(store);
+            remove_covers(&req, [*id]).await;
+            HttpResponse::Ok().body("Book deleted")
+        }
+        None => ApiError::from(StoreError::NotFound).error_response(),
+    }
+}
+
+// Endpoint describing the API, so hitting the root is not a bare 404
+pub async fn api_index() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "links": {
+            "books": "/books",
+            "schema": "/books/schema",
+            "health": "/health",
+        },
+    }))
+}
+
+// Endpoint reporting service health, including the store circuit breaker
+pub async fn health(req: HttpRequest) -> impl Responder {
+    let breaker = req.app_data::<web::Data<CircuitBreaker>>().map(|breaker| breaker.state());
+    let status = match breaker {
+        None | Some(BreakerState::Closed) => "ok",
+        Some(_) => "degraded",
+    };
+    let mut body = serde_json::json!({
+        "status": status,
+        "store_breaker": breaker.unwrap_or(BreakerState::Closed),
+    });
+    if let Some(cache) = req.app_data::<web::Data<ListCache>>() {
+        body["list_cache_hits"] = cache.hits().into();
+    }
+    if let Some(cache) = req.app_data::<web::Data<BookCache>>() {
+        body["book_cache_hits"] = cache.hits().into();
+    }
+    HttpResponse::Ok().json(body)
+}
+
+// Admin endpoint to turn maintenance mode on or off at runtime
+pub async fn set_maintenance(state: web::Json<MaintenanceState>, maintenance: web::Data<Maintenance>) -> impl Responder {
+    info!("set maintenance mode to {}", state.enabled);
+    maintenance.set_enabled(state.enabled);
+    HttpResponse::Ok().json(MaintenanceState {
+        enabled: maintenance.is_enabled(),
+    })
+}
+
+// Admin endpoint to change the log level at runtime, e.g. to "debug" while investigating. Only
+// output of the log crate is covered: no tracing subscriber is installed, so the tracing spans
+// are not reported at any level and there is no tracing filter to reload
+pub async fn set_log_level(state: web::Json<LogLevelState>, log_level: web::Data<LogLevel>) -> impl Responder {
+    info!("set log level to {}", state.level);
+    let Ok(level) = state.level.parse::<LevelFilter>() else {
+        return ApiError::Validation(format!("unknown log level {:?}, expected off, error, warn, info, debug or trace", state.level)).error_response();
+    };
+    log_level.set(level);
+    HttpResponse::Ok().json(LogLevelState {
+        level: log_level.get().as_str().to_lowercase(),
+    })
+}
+
+// Admin endpoint to poll a background job such as an asynchronous import
+pub async fn get_job(id: web::Path<u64>, jobs: web::Data<Jobs>) -> impl Responder {
+    info!("get job");
+    match jobs.get(*id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => ApiError::NotFound("job not found".to_string()).error_response(),
+    }
+}
+
+// Admin endpoint to remove books with the same normalized title and author
+pub async fn dedupe_books(req: HttpRequest, query: web::Query<DedupeQuery>, books: web::Data<Books>) -> impl Responder {
+    info!("dedupe books (dry run: {})", query.dry_run);
+    let mut store = books.write().await;
+    let removed = store.dedupe(query.dry_run);
+    forget_books(&req, removed.values().flatten().copied());
+    drop(store);
+    if !query.dry_run {
+        remove_covers(&req, removed.values().flatten().copied()).await;
+    }
+    HttpResponse::Ok().json(DedupeReport {
+        dry_run: query.dry_run,
+        removed,
+    })
+}
+
+// Admin endpoint to unify author spellings that differ only in case or spacing
+pub async fn normalize_authors(req: HttpRequest, query: web::Query<DedupeQuery>, books: web::Data<Books>) -> impl Responder {
+    info!("normalize authors (dry run: {})", query.dry_run);
+    let mut store = books.write().await;
+    let rewrites = store.normalize_authors(query.dry_run);
+    if !query.dry_run && !rewrites.is_empty() {
+        forget_all_books(&req);
+    }
+    drop(store);
+    HttpResponse::Ok().json(NormalizeAuthorsReport {
+        dry_run: query.dry_run,
+        rewrites,
+    })
+}
+
+// Body accepted by the warmup endpoint
+#[derive(Deserialize, Default)]
+pub struct WarmupRequest {
+    // Searches to prime besides the unfiltered list
+    #[serde(default)]
+    pub queries: Vec<String>,
+}
+
+// Admin endpoint to rebuild the search index and prime the list cache before taking traffic;
+// entries are cached for anonymous callers without a tenant, so with API keys or tenant
+// isolation on no request would hit them and priming is skipped
+pub async fn warmup(req: HttpRequest, body: Option<web::Json<WarmupRequest>>, config: web::Data<Config>, books: web::Data<Books>) -> impl Responder {
+    info!("warmup");
+    let started = Instant::now();
+    let tokens = books.write().await.reindex();
+    let sort = match parse_sort(&config.default_sort) {
+        Ok(sort) => sort,
+        Err(message) => return ApiError::Internal(message).error_response(),
+    };
+    if config.tenant_isolation || !config.api_keys.is_empty() {
+        warn!("warmup skipped priming the list cache: entries are keyed by caller and tenant");
+        return HttpResponse::Ok().json(WarmupReport {
+            queries: 0,
+            tokens,
+            elapsed_micros: started.elapsed().as_micros() as u64,
+        });
+    }
+    let ctx = AuthContext::default();
+    let searches = body.map(|body| body.into_inner().queries).unwrap_or_default();
+    let queries: Vec<ListQuery> = std::iter::once(ListQuery::default())
+        .chain(searches.into_iter().map(|q| ListQuery {
+            q: Some(q),
+            ..ListQuery::default()
+        }))
+        .collect();
+    for query in &queries {
+        let key = ListFlightKey::new(query, &ctx);
+        if let Err(e) = load_books(&req, query, sort.clone(), &ctx, &config, &books, &key).await {
+            return ApiError::from(e).error_response();
+        }
+    }
+    HttpResponse::Ok().json(WarmupReport {
+        queries: queries.len(),
+        tokens,
+        elapsed_micros: started.elapsed().as_micros() as u64,
+    })
+}
+
+// Admin endpoint to verify the store's invariants; violations are reported with a 200,
+// while a store that can't be read at all is a 500
+pub async fn self_check(req: HttpRequest, books: web::Data<Books>) -> Result<HttpResponse, ApiError> {
+    info!("self-check");
+    let store = read_store(&req, &books).await.map_err(|e| ApiError::Internal(format!("self-check could not read the store: {}", e)))?;
+    Ok(HttpResponse::Ok().json(SelfCheckReport {
+        books: store.books.len(),
+        violations: store.check(),
+    }))
+}
+
+// Admin endpoint to rebuild the search index from the current books
+pub async fn reindex_books(books: web::Data<Books>) -> impl Responder {
+    info!("reindex books");
+    let mut store = books.write().await;
+    let started = Instant::now();
+    let tokens = store.reindex();
+    HttpResponse::Ok().json(ReindexReport {
+        books: store.books.len(),
+        tokens,
+        elapsed_micros: started.elapsed().as_micros() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{json_config, query_config};
+    use crate::middleware::{authenticate, head_as_get, require_tenant, API_KEY_HEADER, TENANT_HEADER};
+    use crate::store::{Store, BOOKS};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::RwLock;
+
+    // Wire names of multi-word fields in the convention selected at build time
+    #[cfg(feature = "camel-case")]
+    const ISSUE_NUMBER: &str = "issueNumber";
+    #[cfg(not(feature = "camel-case"))]
+    const ISSUE_NUMBER: &str = "issue_number";
+    #[cfg(feature = "camel-case")]
+    const PUBLISHED_YEAR: &str = "publishedYear";
+    #[cfg(not(feature = "camel-case"))]
+    const PUBLISHED_YEAR: &str = "published_year";
+
+    // Subscriber recording the names of spans created while it is the default
+    struct SpanRecorder {
+        names: Arc<Mutex<Vec<String>>>,
+        next_id: AtomicU64,
+    }
+
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            self.names.lock().unwrap().push(span.metadata().name().to_string());
+            tracing::span::Id::from_u64(self.next_id.fetch_add(1, AtomicOrdering::SeqCst))
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    // Build a stored book for seeding test stores
+    fn sample_book(id: i32, title: &str, author: &str) -> Book {
+        Book {
+            id,
+            title: title.to_string(),
+            author: author.to_string(),
+            genre: None,
+            language: None,
+            kind: BookKind::Book,
+            issue_number: None,
+            position: None,
+            tags: Vec::new(),
+            isbn: None,
+            published_year: None,
+            updated_at: 0,
+            version: 1,
+            owner: None,
+            tenant_id: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_get_books() {
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books").route(web::get().to(get_books)))).await;
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_create_book() {
+        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn test_get_book() {
+        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+        
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+
+        // Other tests share BOOKS and ids are never reissued, so use the id this create got
+        let created: Book = test::read_body_json(test::call_service(&app, req).await).await;
+
+        let req = test::TestRequest::get().uri(&format!("/books/{}", created.id)).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_update_book() {
+        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+        let id = BOOKS.write().await.insert(&NewBook::from(&sample_book(0, "Book Title", "Book Author")), None, None).id;
+        let req = test::TestRequest::put()
+            .uri(&format!("/books/{}", id))
+            .set_json(&NewBook {
+                title: "Updated Book Title".to_string(),
+                author: "Updated Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_delete_book() {
+        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books/{id}").route(web::delete().to(delete_book)))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let _res = test::call_service(&app, req).await;
+
+        let req = test::TestRequest::delete().uri("/books/1").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_create_book_with_known_schema_version() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .insert_header((SCHEMA_VERSION_HEADER, "1"))
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn test_create_book_defaults_to_latest_schema_version() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&NewBook {
+                title: "".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 422, "latest schema rules apply when the header is absent");
+    }
+
+    #[actix_web::test]
+    async fn test_update_book_with_unsupported_schema_version() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+        let req = test::TestRequest::put()
+            .uri("/books/1")
+            .insert_header((SCHEMA_VERSION_HEADER, "99"))
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_schema_version");
+        assert_eq!(body["error"]["message"], "Unsupported schema version: 99");
+    }
+
+    #[actix_web::test]
+    async fn test_create_book_with_unsupported_schema_version() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone())).service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .insert_header((SCHEMA_VERSION_HEADER, "99"))
+            .set_json(serde_json::json!({"title": "Book Title", "author": "Book Author"}))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_schema_version");
+        assert!(store.read().await.books.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_recent_books_puts_updated_book_first() {
+        let store: Books = Arc::new(RwLock::new(
+            (1..=3)
+                .map(|id| Book {
+                    updated_at: id as u64 * 1000,
+                    ..sample_book(id, &format!("Title {}", id), "Author")
+                })
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/recent").route(web::get().to(get_recent_books)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+
+        let req = test::TestRequest::put()
+            .uri("/books/1")
+            .set_json(&NewBook {
+                title: "Updated Title".to_string(),
+                author: "Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let _res = test::call_service(&app, req).await;
+
+        let req = test::TestRequest::get().uri("/books/recent?limit=2").to_request();
+        let recent: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        let ids: Vec<i32> = recent.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
+    #[actix_web::test]
+    async fn test_batch_update_renames_author() {
+        let store: Books = Arc::new(RwLock::new(
+            (1..=3)
+                .map(|id| sample_book(id, &format!("Title {}", id), "J. R. R. Tolkein"))
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone())).service(web::resource("/books").route(web::patch().to(batch_update_books)))).await;
+        let req = test::TestRequest::patch()
+            .uri("/books")
+            .set_json(&BatchUpdate {
+                ids: vec![1, 3, 42],
+                changes: BookChanges {
+                    author: Some("J. R. R. Tolkien".to_string()),
+                    ..Default::default()
+                },
+            })
+            .to_request();
+        let result: BatchUpdateResult = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(result.updated.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(result.missing, vec![42]);
+
+        let req = test::TestRequest::patch()
+            .uri("/books")
+            .insert_header((SCHEMA_VERSION_HEADER, "99"))
+            .set_json(serde_json::json!({"ids": [1], "changes": {"genre": "Fantasy"}}))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_schema_version");
+
+        let store = store.read().await;
+        let authors: Vec<&str> = store.books.iter().map(|b| b.author.as_str()).collect();
+        assert_eq!(authors, vec!["J. R. R. Tolkien", "J. R. R. Tolkein", "J. R. R. Tolkien"]);
+    }
+
+    #[actix_web::test]
+    async fn test_fuzzy_search_tolerates_typos() {
+        let store: Books = Arc::new(RwLock::new(
+            [("The Hobbit", "J.R.R. Tolkien"), ("Tolkein Unauthorized", "Some Critic"), ("Dune", "Frank Herbert")]
+                .iter()
+                .enumerate()
+                .map(|(i, (title, author))| sample_book(i as i32 + 1, title, author))
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store)).service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?q=tolkein").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2]);
+
+        let req = test::TestRequest::get().uri("/books?q=tolkein&fuzzy=true").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2, 1], "exact matches rank above fuzzy ones");
+        assert_eq!(books[1].author, "J.R.R. Tolkien");
+    }
+
+    #[actix_web::test]
+    async fn test_books_schema_requires_title_and_author() {
+        let app = test::init_service(App::new().service(web::resource("/books/schema").route(web::get().to(get_books_schema)))).await;
+        let req = test::TestRequest::get().uri("/books/schema").to_request();
+        let schema: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        for model in ["book", "new_book"] {
+            let required = schema[model]["required"].as_array().unwrap();
+            for field in ["title", "author"] {
+                assert!(required.contains(&serde_json::json!(field)), "{} requires {}", model, field);
+                assert_eq!(schema[model]["properties"][field]["type"], "string");
+                assert_eq!(schema[model]["properties"][field]["minLength"], 1);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_sort_by_multiple_keys() {
+        let store: Books = Arc::new(RwLock::new(
+            [("A", "Herbert"), ("C", "Asimov"), ("B", "Herbert"), ("A", "Asimov")]
+                .iter()
+                .enumerate()
+                .map(|(i, (title, author))| sample_book(i as i32 + 1, title, author))
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store)).service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?sort=author:asc,title:desc").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2, 4, 3, 1]);
+
+        for sort in ["publisher:asc", "title:sideways"] {
+            let req = test::TestRequest::get().uri(&format!("/books?sort={}", sort)).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 400, "sort={}", sort);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "invalid_parameter", "sort={}", sort);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_equal_sort_keys_order_by_id() {
+        // Imported books can sit in the store out of id order
+        let store: Books = Arc::new(RwLock::new(
+            [(7, "Herbert"), (3, "Herbert"), (5, "Asimov"), (1, "Herbert"), (2, "Asimov")]
+                .iter()
+                .map(|(id, author)| sample_book(*id, "Same title", author))
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store)).service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        for (sort, expected) in [("author:asc", [2, 5, 1, 3, 7]), ("author:desc", [1, 3, 7, 2, 5]), ("title:asc", [1, 2, 3, 5, 7])] {
+            for _ in 0..2 {
+                let req = test::TestRequest::get().uri(&format!("/books?sort={}", sort)).to_request();
+                let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+                assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), expected, "sort={}", sort);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_suggest_authors() {
+        let store: Books = Arc::new(RwLock::new(
+            ["Terry Pratchett", "Tolkien", "terry pratchett", "Frank Herbert", "Ted Chiang"]
+                .iter()
+                .enumerate()
+                .map(|(i, author)| sample_book(i as i32 + 1, &format!("Title {}", i), author))
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/authors/suggest").route(web::get().to(suggest_authors)))).await;
+
+        let req = test::TestRequest::get().uri("/authors/suggest?prefix=t").to_request();
+        let authors: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(authors, vec!["Ted Chiang", "Terry Pratchett", "Tolkien"]);
+
+        let req = test::TestRequest::get().uri("/authors/suggest?prefix=zz").to_request();
+        let authors: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert!(authors.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_create_book_prefer_return() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let create = |prefer: &str| {
+            test::TestRequest::post()
+                .uri("/books")
+                .insert_header(("Prefer", prefer))
+                .set_json(&NewBook {
+                    title: "Book Title".to_string(),
+                    author: "Book Author".to_string(),
+                    genre: None,
+                    language: None,
+                    kind: None,
+                    issue_number: None,
+                    isbn: None,
+                    published_year: None,
+                })
+                .to_request()
+        };
+
+        let res = test::call_service(&app, create("return=minimal")).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/books/1");
+        assert!(test::read_body(res).await.is_empty());
+
+        let res = test::call_service(&app, create("return=representation")).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/books/2");
+        let book: Book = test::read_body_json(res).await;
+        assert_eq!(book.title, "Book Title");
+    }
+
+    #[actix_web::test]
+    async fn test_get_books_emits_phase_spans() {
+        let names = Arc::new(Mutex::new(vec![]));
+        let _guard = tracing::subscriber::set_default(SpanRecorder {
+            names: names.clone(),
+            next_id: AtomicU64::new(1),
+        });
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store)).service(web::resource("/books").route(web::get().to(get_books)))).await;
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(*names.lock().unwrap(), vec!["get_books.lock", "get_books.filter", "get_books.serialize"]);
+    }
+
+    #[actix_web::test]
+    async fn test_delete_book_if_match() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            Book {
+                version: 3,
+                ..sample_book(1, "Title", "Author")
+            },
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone())).service(web::resource("/books/{id}").route(web::delete().to(delete_book)))).await;
+
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((header::IF_MATCH, "\"2\"")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 412);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "precondition_failed");
+        assert_eq!(store.read().await.books.len(), 1);
+
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((header::IF_MATCH, "\"3\"")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert!(store.read().await.books.is_empty());
+
+        for uri in ["/books/1", "/books/abc"] {
+            let res = test::call_service(&app, test::TestRequest::delete().uri(uri).to_request()).await;
+            assert_eq!(res.status(), 404, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "not_found", "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_ids_serialized_as_strings() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(7, "Title", "Author")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books?id_as_string=true").to_request();
+        let books: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books[0]["id"], serde_json::json!("7"));
+        assert_eq!(books[0]["version"], serde_json::json!(1));
+
+        let req = test::TestRequest::get().uri("/books/7?id_as_string=true").to_request();
+        let book: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(book["id"], serde_json::json!("7"));
+
+        let req = test::TestRequest::get().uri("/books/7").to_request();
+        let book: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(book["id"], serde_json::json!(7));
+    }
+
+    #[actix_web::test]
+    async fn test_empty_body_is_rejected_cleanly() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author")])));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+
+        let requests = [
+            test::TestRequest::post().uri("/books").to_request(),
+            test::TestRequest::put().uri("/books/1").insert_header(ContentType::json()).to_request(),
+        ];
+        for req in requests {
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 400);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "empty_body");
+        }
+
+        let req = test::TestRequest::put().uri("/books/1").insert_header(ContentType::json()).set_payload("{\"title\":").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_json");
+    }
+
+    #[actix_web::test]
+    async fn test_get_books_items_range() {
+        let store: Books = Arc::new(RwLock::new((1..=5).map(|id| sample_book(id, &format!("Title {}", id), "Author")).collect()));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store)).service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books").insert_header((header::RANGE, "items=1-2")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 206);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "items 1-2/5");
+        let books: Vec<Book> = test::read_body_json(res).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2, 3]);
+
+        let req = test::TestRequest::get().uri("/books").insert_header((header::RANGE, "items=5-9")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 416);
+        assert_eq!(res.headers().get(header::CONTENT_RANGE).unwrap(), "items */5");
+    }
+
+    #[actix_web::test]
+    async fn test_book_history_records_create_and_updates() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))
+        .service(web::resource("/books/{id}/history").route(web::get().to(get_book_history)))).await;
+
+        for (i, title) in ["First", "Second", "Third"].iter().enumerate() {
+            let req = if i == 0 { test::TestRequest::post().uri("/books") } else { test::TestRequest::put().uri("/books/1") };
+            let req = req
+                .set_json(&NewBook {
+                    title: title.to_string(),
+                    author: "Author".to_string(),
+                    genre: None,
+                    language: None,
+                    kind: None,
+                    issue_number: None,
+                    isbn: None,
+                    published_year: None,
+                })
+                .to_request();
+            let res = test::call_service(&app, req).await;
+            assert!(res.status().is_success());
+        }
+
+        let req = test::TestRequest::get().uri("/books/1/history").to_request();
+        let history: Vec<HistoryEntry> = test::call_and_read_body_json(&app, req).await;
+        let changes: Vec<(ChangeKind, &str, u32)> = history.iter().map(|e| (e.change, e.book.title.as_str(), e.book.version)).collect();
+        assert_eq!(changes, vec![(ChangeKind::Created, "First", 1), (ChangeKind::Updated, "Second", 2), (ChangeKind::Updated, "Third", 3)]);
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        let req = test::TestRequest::get().uri("/books/2/history").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_head_on_derived_endpoints() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author"), sample_book(2, "Other", "author")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/count").wrap(from_fn(head_as_get)).route(web::get().to(count_books)))
+        .service(web::resource("/books/stats").wrap(from_fn(head_as_get)).route(web::get().to(get_books_stats)))).await;
+
+        for uri in ["/books/count", "/books/stats"] {
+            let req = test::TestRequest::default().method(actix_web::http::Method::HEAD).uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 200, "HEAD {}", uri);
+            assert_eq!(res.headers().get(TOTAL_COUNT_HEADER).unwrap(), "2");
+            assert!(test::read_body(res).await.is_empty());
+        }
+
+        let req = test::TestRequest::get().uri("/books/count").to_request();
+        let count: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(count["count"], 2);
+
+        let req = test::TestRequest::get().uri("/books/stats").to_request();
+        let stats: BookStats = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(stats.distinct_authors, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_dedupe_books() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(3, "Dune", "Frank Herbert"),
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(4, " dune ", "frank  herbert"),
+            sample_book(5, "EMMA", "Jane Austen"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone()))
+        .route("/admin/dedupe", web::post().to(dedupe_books))).await;
+
+        let req = test::TestRequest::post().uri("/admin/dedupe?dry_run=true").to_request();
+        let report: DedupeReport = test::call_and_read_body_json(&app, req).await;
+        assert!(report.dry_run);
+        assert_eq!(report.removed.get(&1), Some(&vec![3, 4]));
+        assert_eq!(report.removed.get(&2), Some(&vec![5]));
+        assert_eq!(store.read().await.books.len(), 5);
+
+        let req = test::TestRequest::post().uri("/admin/dedupe").to_request();
+        let report: DedupeReport = test::call_and_read_body_json(&app, req).await;
+        assert!(!report.dry_run);
+        assert_eq!(report.removed.len(), 2);
+        let mut survivors: Vec<i32> = store.read().await.books.iter().map(|b| b.id).collect();
+        survivors.sort();
+        assert_eq!(survivors, vec![1, 2]);
+    }
+
+    #[actix_web::test]
+    async fn test_normalize_authors_unifies_case_variants() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "The Hobbit", "j.r.r. tolkien"),
+            sample_book(2, "The Silmarillion", "J.R.R. Tolkien"),
+            sample_book(3, "Dune", "Frank Herbert"),
+            sample_book(4, "Dune Messiah", "FRANK HERBERT"),
+            sample_book(5, "Children of Dune", "frank  herbert"),
+            sample_book(6, "Emma", "Jane Austen"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone()))
+        .route("/admin/normalize-authors", web::post().to(normalize_authors))).await;
+        let authors = || async { store.read().await.books.iter().map(|b| b.author.clone()).collect::<Vec<_>>() };
+
+        let req = test::TestRequest::post().uri("/admin/normalize-authors?dry_run=true").to_request();
+        let report: NormalizeAuthorsReport = test::call_and_read_body_json(&app, req).await;
+        assert!(report.dry_run);
+        assert_eq!(report.rewrites.len(), 2);
+        assert_eq!(authors().await[0], "j.r.r. tolkien");
+
+        let req = test::TestRequest::post().uri("/admin/normalize-authors").to_request();
+        let report: NormalizeAuthorsReport = test::call_and_read_body_json(&app, req).await;
+        // One spelling each, so the mixed-case ones win
+        assert_eq!(report.rewrites[0].author, "Frank Herbert");
+        assert_eq!(report.rewrites[0].variants, ["FRANK HERBERT", "frank  herbert"]);
+        assert_eq!(report.rewrites[0].books, 2);
+        assert_eq!(report.rewrites[1].author, "J.R.R. Tolkien");
+        assert_eq!(authors().await, ["J.R.R. Tolkien", "J.R.R. Tolkien", "Frank Herbert", "Frank Herbert", "Frank Herbert", "Jane Austen"]);
+        assert_eq!(store.read().await.books[0].version, 2);
+    }
+
+    #[cfg(feature = "search-index")]
+    #[actix_web::test]
+    async fn test_reindex_after_direct_store_mutation() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .route("/admin/reindex", web::post().to(reindex_books))).await;
+        let search = |q: &str| test::TestRequest::get().uri(&format!("/books?q={}", q)).to_request();
+
+        // Bypass the store methods so the index goes stale
+        store.write().await.books.push(sample_book(2, "Emma", "Jane Austen"));
+        let found: Vec<Book> = test::call_and_read_body_json(&app, search("emma")).await;
+        assert!(found.is_empty());
+
+        let req = test::TestRequest::post().uri("/admin/reindex").to_request();
+        let report: ReindexReport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report.books, 2);
+        assert_eq!(report.tokens, 6);
+
+        let found: Vec<Book> = test::call_and_read_body_json(&app, search("emma")).await;
+        assert_eq!(found.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2]);
+
+        // Writes through the store keep the index current
+        store.write().await.update(2, |book| book.title = "Persuasion".to_string());
+        let found: Vec<Book> = test::call_and_read_body_json(&app, search("persua")).await;
+        assert_eq!(found.len(), 1);
+        let found: Vec<Book> = test::call_and_read_body_json(&app, search("emma")).await;
+        assert!(found.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_get_books_pagination_links() {
+        let store: Books = Arc::new(RwLock::new((1..=10).map(|id| sample_book(id, "Title", "Author")).collect()));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?q=title&limit=3&offset=3").to_request();
+        let res = test::call_service(&app, req).await;
+        let link = res.headers().get(header::LINK).unwrap().to_str().unwrap().to_string();
+        assert_eq!(link, concat!(
+            "</books?q=title&limit=3&offset=0>; rel=\"first\", ",
+            "</books?q=title&limit=3&offset=0>; rel=\"prev\", ",
+            "</books?q=title&limit=3&offset=6>; rel=\"next\", ",
+            "</books?q=title&limit=3&offset=9>; rel=\"last\"",
+        ));
+        let page: Vec<Book> = test::read_body_json(res).await;
+        assert_eq!(page.iter().map(|b| b.id).collect::<Vec<_>>(), vec![4, 5, 6]);
+
+        let req = test::TestRequest::get().uri("/books?limit=3").to_request();
+        let res = test::call_service(&app, req).await;
+        let link = res.headers().get(header::LINK).unwrap().to_str().unwrap();
+        assert!(!link.contains("rel=\"prev\""));
+        assert!(link.contains("</books?limit=3&offset=0>; rel=\"first\""));
+        assert!(link.contains("</books?limit=3&offset=3>; rel=\"next\""));
+    }
+
+    #[actix_web::test]
+    async fn test_malformed_body_is_400_and_invalid_body_is_422() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let post = |payload: &'static str| test::TestRequest::post().uri("/books").insert_header(ContentType::json()).set_payload(payload).to_request();
+
+        let res = test::call_service(&app, post("{\"title\": \"Dune\",")).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_json");
+
+        for payload in ["{\"title\": \" \", \"author\": \"Frank Herbert\"}", "{\"title\": \"Dune\"}"] {
+            let res = test::call_service(&app, post(payload)).await;
+            assert_eq!(res.status(), 422, "{}", payload);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "validation_failed");
+        }
+        assert!(store.read().await.books.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn test_books_are_scoped_to_their_owner() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            admin_api_key: Some("root-key".to_string()),
+            api_keys: [("alice-key", "alice"), ("bob-key", "bob")].into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().wrap(from_fn(authenticate)).app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)).route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+        let list = |key: &str| test::TestRequest::get().uri("/books").insert_header((API_KEY_HEADER, key.to_string())).to_request();
+
+        for (key, title) in [("alice-key", "Alice's Book"), ("bob-key", "Bob's Book")] {
+            let req = test::TestRequest::post()
+                .uri("/books")
+                .insert_header((API_KEY_HEADER, key))
+                .set_json(&NewBook {
+                    title: title.to_string(),
+                    author: "Author".to_string(),
+                    genre: None,
+                    language: None,
+                    kind: None,
+                    issue_number: None,
+                    isbn: None,
+                    published_year: None,
+                })
+                .to_request();
+            let book: Book = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(book.owner.as_deref(), Some(&key[..key.len() - 4]));
+        }
+
+        let alice: Vec<Book> = test::call_and_read_body_json(&app, list("alice-key")).await;
+        assert_eq!(alice.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), vec!["Alice's Book"]);
+        let bob: Vec<Book> = test::call_and_read_body_json(&app, list("bob-key")).await;
+        assert_eq!(bob.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), vec!["Bob's Book"]);
+        let all: Vec<Book> = test::call_and_read_body_json(&app, list("root-key")).await;
+        assert_eq!(all.len(), 2);
+
+        let req = test::TestRequest::get().uri("/books/1").insert_header((API_KEY_HEADER, "bob-key")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+        let req = test::TestRequest::get().uri("/books/1").insert_header((API_KEY_HEADER, "root-key")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::get().uri("/books").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn test_tenants_are_isolated() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            tenant_isolation: true,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().wrap(from_fn(require_tenant)).app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)).route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)).route(web::put().to(update_book)).route(web::delete().to(delete_book)))).await;
+        let new_book = |title: &str| NewBook {
+            title: title.to_string(),
+            author: "Author".to_string(),
+            genre: None,
+            language: None,
+            kind: None,
+            issue_number: None,
+            isbn: None,
+            published_year: None,
+        };
+
+        let req = test::TestRequest::post().uri("/books").insert_header((TENANT_HEADER, "acme")).set_json(new_book("Acme Book")).to_request();
+        let book: Book = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(book.tenant_id.as_deref(), Some("acme"));
+
+        let req = test::TestRequest::get().uri("/books").insert_header((TENANT_HEADER, "globex")).to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert!(books.is_empty());
+        let req = test::TestRequest::get().uri("/books/1").insert_header((TENANT_HEADER, "globex")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+        let req = test::TestRequest::put().uri("/books/1").insert_header((TENANT_HEADER, "globex")).set_json(new_book("Hijacked")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((TENANT_HEADER, "globex")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        let req = test::TestRequest::get().uri("/books/1").insert_header((TENANT_HEADER, "acme")).to_request();
+        let book: Book = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(book.title, "Acme Book");
+
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "missing_tenant");
+    }
+
+    #[actix_web::test]
+    async fn test_similar_books_include_author_siblings() {
+        let with_genre = |book: Book, genre: &str| Book {
+            genre: Some(genre.to_string()),
+            ..book
+        };
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            with_genre(sample_book(1, "Dune", "Frank Herbert"), "sci-fi"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "Dune Messiah", "frank herbert"),
+            with_genre(sample_book(4, "Foundation", "Isaac Asimov"), "Sci-Fi"),
+            sample_book(5, "Children of Dune", "Frank Herbert"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}/similar").route(web::get().to(get_similar_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books/1/similar").to_request();
+        let similar: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(similar.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 5, 4]);
+
+        let req = test::TestRequest::get().uri("/books/1/similar?limit=1").to_request();
+        let similar: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(similar.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3]);
+
+        let req = test::TestRequest::get().uri("/books/42/similar").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_default_sort_applies_without_sort_param() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Emma", "Jane Austen"),
+            sample_book(2, "Dune", "Frank Herbert"),
+            sample_book(3, "Foundation", "Isaac Asimov"),
+        ])));
+        let config = Config::from_lookup(|name| (name == "DEFAULT_SORT").then(|| "title:asc".to_string())).unwrap();
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), vec!["Dune", "Emma", "Foundation"]);
+
+        let req = test::TestRequest::get().uri("/books?sort=id:desc").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 2, 1]);
+
+        let err = Config::from_lookup(|name| (name == "DEFAULT_SORT").then(|| "pages:asc".to_string())).unwrap_err();
+        assert!(err.contains("DEFAULT_SORT"));
+    }
+
+    #[actix_web::test]
+    async fn test_books_exist_flags_known_pairs() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/exists").route(web::post().to(books_exist)))).await;
+        let pair = |title: &str, author: &str| NewBook {
+            title: title.to_string(),
+            author: author.to_string(),
+            genre: None,
+            language: None,
+            kind: None,
+            issue_number: None,
+            isbn: None,
+            published_year: None,
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/books/exists")
+            .set_json(vec![pair("  DUNE ", "frank  herbert"), pair("Foundation", "Isaac Asimov"), pair("Emma", "Jane Austen")])
+            .to_request();
+        let results: Vec<ExistsResult> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(results, vec![
+            ExistsResult { exists: true, id: Some(1) },
+            ExistsResult { exists: false, id: None },
+            ExistsResult { exists: true, id: Some(2) },
+        ]);
+    }
+
+    #[actix_web::test]
+    async fn test_read_gives_up_on_contended_store() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author")])));
+        let config = Config {
+            store_read_timeout_ms: 20,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let writer = store.write().await;
+        for uri in ["/books", "/books/1"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 503, "{}", uri);
+            assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "1");
+        }
+        drop(writer);
+
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_pagination_page_numbers() {
+        let pages = |total, limit, offset| {
+            let pagination = Pagination::new(total, limit, offset);
+            (pagination.total_pages, pagination.current_page)
+        };
+        assert_eq!(pages(0, Some(10), 0), (0, 0));
+        assert_eq!(pages(0, None, 0), (0, 0));
+        assert_eq!(pages(5, Some(10), 0), (1, 1));
+        assert_eq!(pages(5, None, 0), (1, 1));
+        assert_eq!(pages(10, Some(5), 0), (2, 1));
+        assert_eq!(pages(11, Some(5), 5), (3, 2));
+        assert_eq!(pages(11, Some(5), 10), (3, 3));
+        assert_eq!(pages(11, Some(5), 7), (3, 2));
+    }
+
+    #[actix_web::test]
+    async fn test_get_books_envelope_includes_pages() {
+        let store: Books = Arc::new(RwLock::new((1..=7).map(|id| sample_book(id, "Title", "Author")).collect()));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?envelope=true&limit=3&offset=3").to_request();
+        let page: Envelope<Vec<Book>> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page.data.iter().map(|b| b.id).collect::<Vec<_>>(), vec![4, 5, 6]);
+        assert_eq!(page.pagination, Pagination {
+            total: 7,
+            limit: Some(3),
+            offset: 3,
+            total_pages: 3,
+            current_page: 2,
+        });
+    }
+
+    #[actix_web::test]
+    async fn test_tag_genre_by_author() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "Dune Messiah", "frank herbert"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/tag-genre").route(web::post().to(tag_genre)))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/books/tag-genre")
+            .set_json(serde_json::json!({"filter": {"author": "Frank Herbert"}, "genre": "sci-fi"}))
+            .to_request();
+        let result: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(result["updated"], 2);
+        let store = store.read().await;
+        let genres: Vec<(i32, Option<&str>)> = store.books.iter().map(|b| (b.id, b.genre.as_deref())).collect();
+        assert_eq!(genres, vec![(1, Some("sci-fi")), (2, None), (3, Some("sci-fi"))]);
+        assert_eq!(store.books[0].version, 2);
+    }
+
+    #[actix_web::test]
+    async fn test_lookup_book_by_title_and_author() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "emma", "jane austen"),
+        ])));
+        let app = test::init_service(App::new().wrap(from_fn(crate::middleware::problem_details)).app_data(web::Data::new(store))
+        .service(web::resource("/books/lookup").route(web::get().to(lookup_book)))).await;
+        let lookup = |query: &str| test::TestRequest::get().uri(&format!("/books/lookup?{}", query)).to_request();
+
+        let book: Book = test::call_and_read_body_json(&app, lookup("title=DUNE&author=frank%20herbert")).await;
+        assert_eq!(book.id, 1);
+
+        let res = test::call_service(&app, lookup("title=Foundation&author=Isaac%20Asimov")).await;
+        assert_eq!(res.status(), 404);
+
+        let res = test::call_service(&app, lookup("title=Emma&author=Jane%20Austen")).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "ambiguous");
+        assert_eq!(body["error"]["ids"], serde_json::json!([2, 3]));
+
+        let req = test::TestRequest::get()
+            .uri("/books/lookup?title=Emma&author=Jane%20Austen")
+            .insert_header((header::ACCEPT, crate::errors::PROBLEM_JSON))
+            .to_request();
+        let problem: serde_json::Value = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!((&problem["code"], &problem["ids"]), (&serde_json::json!("ambiguous"), &serde_json::json!([2, 3])));
+    }
+
+    #[actix_web::test]
+    async fn test_contended_store_opens_breaker_shown_in_health() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            store_read_timeout_ms: 5,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .app_data(web::Data::new(config))
+        .app_data(web::Data::new(CircuitBreaker::new(2, Duration::from_secs(60))))
+        .app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/count").route(web::get().to(count_books)))
+        .route("/health", web::get().to(health))).await;
+
+        let writer = store.write().await;
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri("/books/count").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 503);
+        }
+        drop(writer);
+        // The lock is free again but the open breaker still fails fast
+        let req = test::TestRequest::get().uri("/books/count").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 503);
+
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(health, serde_json::json!({"status": "degraded", "store_breaker": "open"}));
+    }
+
+    #[actix_web::test]
+    async fn test_language_tags_are_validated_and_filterable() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)).route(web::post().to(create_book)))).await;
+        let create = |title: &str, language: Option<&str>| {
+            test::TestRequest::post()
+                .uri("/books")
+                .set_json(&NewBook {
+                    title: title.to_string(),
+                    author: "Author".to_string(),
+                    genre: None,
+                    language: language.map(str::to_string),
+                    kind: None,
+                    issue_number: None,
+                    isbn: None,
+                    published_year: None,
+                })
+                .to_request()
+        };
+
+        for (title, language) in [("Colour", Some("en-GB")), ("Color", Some("en")), ("Couleur", Some("fr-CA")), ("Untagged", None)] {
+            let res = test::call_service(&app, create(title, language)).await;
+            assert_eq!(res.status(), 201, "{:?}", language);
+        }
+        let res = test::call_service(&app, create("Bad", Some("english_uk"))).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_language");
+
+        let req = test::TestRequest::get().uri("/books?language=en").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), vec!["Colour", "Color"]);
+        let req = test::TestRequest::get().uri("/books?language=FR-ca").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), vec!["Couleur"]);
+        let req = test::TestRequest::get().uri("/books?language=e").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_cover_upload_and_retrieve() {
+        let dir = std::env::temp_dir().join(format!("restapi-rust-covers-{}", std::process::id()));
+        let config = Config {
+            cover_dir: dir.clone(),
+            cover_max_bytes: 16,
+            ..Config::default()
+        };
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::delete().to(delete_book)))
+        .service(web::resource("/books/{id}/cover").route(web::put().to(put_book_cover)).route(web::get().to(get_book_cover)))).await;
+        let upload = |content_type: &str, body: &'static [u8]| {
+            test::TestRequest::put().uri("/books/1/cover").insert_header((header::CONTENT_TYPE, content_type.to_string())).set_payload(body).to_request()
+        };
+
+        let req = test::TestRequest::get().uri("/books/1/cover").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+
+        assert_eq!(test::call_service(&app, upload("image/png", b"\x89PNG fake")).await.status(), 204);
+        let req = test::TestRequest::get().uri("/books/1/cover").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "image/png");
+        assert_eq!(test::read_body(res).await, &b"\x89PNG fake"[..]);
+
+        let res = test::call_service(&app, upload("image/gif", b"GIF89a")).await;
+        assert_eq!(res.status(), 415);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unsupported_media_type");
+        let res = test::call_service(&app, upload("image/jpeg", b"far more than sixteen bytes")).await;
+        assert_eq!(res.status(), 413);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "payload_too_large");
+
+        // Deleting the book takes its cover along
+        let req = test::TestRequest::delete().uri("/books/1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert!(!dir.join("1.png").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_magazines_require_an_issue_number() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let create = |body: serde_json::Value| test::TestRequest::post().uri("/books").set_json(body).to_request();
+
+        let res = test::call_service(&app, create(serde_json::json!({"title": "Wired", "author": "Staff", "kind": "magazine", ISSUE_NUMBER: 7}))).await;
+        assert_eq!(res.status(), 201);
+        let magazine: Book = test::read_body_json(res).await;
+        assert_eq!((magazine.kind, magazine.issue_number), (BookKind::Magazine, Some(7)));
+
+        let res = test::call_service(&app, create(serde_json::json!({"title": "Wired", "author": "Staff", "kind": "magazine"}))).await;
+        assert_eq!(res.status(), 422);
+
+        let res = test::call_service(&app, create(serde_json::json!({"title": "Dune", "author": "Frank Herbert"}))).await;
+        assert_eq!(res.status(), 201);
+        let book: Book = test::read_body_json(res).await;
+        assert_eq!((book.kind, book.issue_number), (BookKind::Book, None));
+
+        let res = test::call_service(&app, create(serde_json::json!({"title": "Dune", "author": "Frank Herbert", "kind": "comic"}))).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_kind");
+    }
+
+    #[actix_web::test]
+    async fn test_bulk_create_reports_created_count() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/bulk").route(web::post().to(bulk_create_books)))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/books/bulk")
+            .set_json(serde_json::json!([
+                {"title": "Dune", "author": "Frank Herbert"},
+                {"title": "Emma", "author": "Jane Austen"},
+                {"title": "Ubik", "author": "Philip K. Dick"}
+            ]))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 201);
+        let count: usize = res.headers().get(CREATED_COUNT_HEADER).unwrap().to_str().unwrap().parse().unwrap();
+        let created: Vec<Book> = test::read_body_json(res).await;
+        assert_eq!(count, created.len());
+        assert_eq!(count, 3);
+
+        let req = test::TestRequest::post()
+            .uri("/books/bulk")
+            .set_json(serde_json::json!([{"title": "Solaris", "author": "Stanislaw Lem"}, {"title": " ", "author": "Nobody"}]))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 422);
+        assert_eq!(store.read().await.books.len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_reorder_changes_position_sorted_list() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "First", "Author"),
+            sample_book(2, "Second", "Author"),
+            sample_book(3, "Third", "Author"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/reorder").route(web::post().to(reorder_books)))).await;
+        let titles = |books: Vec<Book>| books.into_iter().map(|b| b.title).collect::<Vec<_>>();
+
+        let req = test::TestRequest::post().uri("/books/reorder").set_json(serde_json::json!({"order": [3, 1, 2]})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::get().uri("/books?sort=position").to_request();
+        assert_eq!(titles(test::call_and_read_body_json(&app, req).await), vec!["Third", "First", "Second"]);
+
+        let req = test::TestRequest::post().uri("/books/reorder").set_json(serde_json::json!({"order": [2, 3]})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::get().uri("/books?sort=position").to_request();
+        assert_eq!(titles(test::call_and_read_body_json(&app, req).await), vec!["Second", "Third", "First"]);
+
+        let req = test::TestRequest::post().uri("/books/reorder").set_json(serde_json::json!({"order": [1, 9]})).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unknown_ids");
+        assert_eq!(body["error"]["ids"], serde_json::json!([9]));
+        let req = test::TestRequest::post().uri("/books/reorder").set_json(serde_json::json!({"order": [1, 2, 1]})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["error"]["code"], "duplicate_ids");
+        assert_eq!(body["error"]["ids"], serde_json::json!([1]));
+        let req = test::TestRequest::get().uri("/books?sort=position").to_request();
+        assert_eq!(titles(test::call_and_read_body_json(&app, req).await), vec!["Second", "Third", "First"]);
+    }
+
+    #[actix_web::test]
+    async fn test_stats_etag_returns_304_until_a_write() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author")])));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/stats").route(web::get().to(get_books_stats)))).await;
+        let stats = |etag: Option<&str>| {
+            let mut req = test::TestRequest::get().uri("/books/stats");
+            if let Some(etag) = etag {
+                req = req.insert_header((header::IF_NONE_MATCH, etag.to_string()));
+            }
+            req.to_request()
+        };
+
+        let res = test::call_service(&app, stats(None)).await;
+        assert_eq!(res.status(), 200);
+        let etag = res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        let res = test::call_service(&app, stats(Some(&etag))).await;
+        assert_eq!(res.status(), 304);
+        assert_eq!(res.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+        assert!(test::read_body(res).await.is_empty());
+
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "New", "author": "Writer"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+        let res = test::call_service(&app, stats(Some(&etag))).await;
+        assert_eq!(res.status(), 200);
+        assert_ne!(res.headers().get(header::ETAG).unwrap().to_str().unwrap(), etag);
+    }
+
+    #[actix_web::test]
+    async fn test_strict_mode_rejects_unknown_query_params() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Title", "Author")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/lookup").route(web::get().to(lookup_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books?autho=Author").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::get().uri("/books?autho=Author&q=title&strict=true").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unknown_parameters");
+        assert_eq!(body["error"]["message"], "unrecognized query parameters: autho");
+
+        let req = test::TestRequest::get().uri("/books/lookup?title=Title&author=Author&strict=true").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let config = Config {
+            strict_query: true,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+        let req = test::TestRequest::get().uri("/books?autho=Author").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_duplicates_report_groups_matching_books() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/duplicates").route(web::get().to(get_duplicate_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books/duplicates").to_request();
+        let groups: Vec<DuplicateGroup> = test::call_and_read_body_json(&app, req).await;
+        assert!(groups.is_empty());
+
+        *store.write().await = Store::from(vec![
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "  dune ", "FRANK herbert"),
+            sample_book(4, "Ubik", "Philip K. Dick"),
+            sample_book(5, "emma", "jane austen"),
+        ]);
+        let req = test::TestRequest::get().uri("/books/duplicates").to_request();
+        let groups: Vec<DuplicateGroup> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(groups.len(), 2);
+        assert_eq!((groups[0].title.as_str(), groups[0].ids.clone()), ("Dune", vec![1, 3]));
+        assert_eq!((groups[1].title.as_str(), groups[1].ids.clone()), ("Emma", vec![2, 5]));
+    }
+
+    #[actix_web::test]
+    async fn test_longest_titles_count_unicode_scalars() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Emma", "Jane Austen"),
+            // Six scalar values but twelve bytes
+            sample_book(2, "Ørkén!", "Author"),
+            sample_book(3, "Dune Messiah", "Frank Herbert"),
+            sample_book(4, "Ubik", "Philip K. Dick"),
+            sample_book(5, "Solaris", "Stanislaw Lem"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/longest-titles").route(web::get().to(get_longest_titles)))).await;
+
+        let req = test::TestRequest::get().uri("/books/longest-titles?limit=4").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 5, 2, 1]);
+
+        let req = test::TestRequest::get().uri("/books/longest-titles?limit=1000").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.len(), 5);
+    }
+
+    #[actix_web::test]
+    async fn test_search_results_are_capped() {
+        let config = Config {
+            max_search_results: 2,
+            ..Config::default()
+        };
+        let store: Books = Arc::new(RwLock::new(Store::from(
+            (1..=4).map(|id| sample_book(id, &format!("Dune {}", id), "Frank Herbert")).collect::<Vec<_>>(),
+        )));
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?q=dune").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(RESULTS_TRUNCATED_HEADER).unwrap(), "true");
+        let books: Vec<Book> = test::read_body_json(res).await;
+        assert_eq!(books.len(), 2);
+
+        let req = test::TestRequest::get().uri("/books?q=dune&envelope=true").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["truncated"], true);
+        assert_eq!(body["pagination"]["total"], 2);
+
+        // Only searches are capped
+        let req = test::TestRequest::get().uri("/books?envelope=true").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(!res.headers().contains_key(RESULTS_TRUNCATED_HEADER));
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 4);
+        assert!(body.get("truncated").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_tags_are_added_removed_and_filterable() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/{id}/tags").route(web::post().to(update_book_tags)))).await;
+        let tag = |id: i32, changes: serde_json::Value| test::TestRequest::post().uri(&format!("/books/{}/tags", id)).set_json(changes).to_request();
+
+        let book: Book = test::call_and_read_body_json(&app, tag(1, serde_json::json!({"add": ["SciFi", "classic", "scifi"]}))).await;
+        assert_eq!(book.tags, vec!["SciFi", "classic"]);
+        let book: Book = test::call_and_read_body_json(&app, tag(1, serde_json::json!({"add": ["Desert"], "remove": ["CLASSIC"]}))).await;
+        assert_eq!(book.tags, vec!["SciFi", "Desert"]);
+        let book: Book = test::call_and_read_body_json(&app, tag(2, serde_json::json!({"add": ["classic"]}))).await;
+        assert_eq!(book.tags, vec!["classic"]);
+
+        let req = test::TestRequest::get().uri("/books?tag=scifi").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+        let req = test::TestRequest::get().uri("/books?tag=Classic").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![2]);
+
+        assert_eq!(test::call_service(&app, tag(9, serde_json::json!({"add": ["x"]}))).await.status(), 404);
+        assert_eq!(test::call_service(&app, tag(1, serde_json::json!({"add": [" "]}))).await.status(), 422);
+    }
+
+    #[actix_web::test]
+    async fn test_warmup_primes_the_list_cache() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(ListCache::new(16))).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .route("/admin/warmup", web::post().to(warmup))
+        .route("/health", web::get().to(health))).await;
+
+        let req = test::TestRequest::post().uri("/admin/warmup").set_json(serde_json::json!({"queries": ["dune"]})).to_request();
+        let report: WarmupReport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report.queries, 2);
+
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.len(), 2);
+        let req = test::TestRequest::get().uri("/books?q=dune").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![1]);
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let health: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(health["list_cache_hits"], 2);
+    }
+
+    #[actix_web::test]
+    async fn test_warmup_skips_priming_when_entries_are_per_tenant() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let config = Config {
+            tenant_isolation: true,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(ListCache::new(16))).app_data(web::Data::new(store))
+        .route("/admin/warmup", web::post().to(warmup))).await;
+
+        let req = test::TestRequest::post().uri("/admin/warmup").set_json(serde_json::json!({"queries": ["dune"]})).to_request();
+        let report: WarmupReport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report.queries, 0);
+    }
+
+    #[actix_web::test]
+    async fn test_list_cache_serves_stale_while_revalidating() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let config = Config {
+            list_cache_swr_secs: 60,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(ListCache::new(16))).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+        let list = || async { test::call_and_read_body_json::<_, _, Vec<Book>>(&app, test::TestRequest::get().uri("/books").to_request()).await.len() };
+
+        assert_eq!(list().await, 1);
+        store.write().await.insert_with_id(sample_book(2, "Emma", "Jane Austen"));
+        // The write outdated the cached list, which is still served while it is recomputed
+        assert_eq!(list().await, 1);
+        let mut refreshed = false;
+        for _ in 0..100 {
+            if list().await == 2 {
+                refreshed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(refreshed, "the stale list was never refreshed");
+    }
+
+    #[actix_web::test]
+    async fn test_weak_and_strong_etags() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)).route(web::delete().to(delete_book)))).await;
+        let get = |uri: &str, if_none_match: Option<&str>| {
+            let mut req = test::TestRequest::get().uri(uri);
+            if let Some(tag) = if_none_match {
+                req = req.insert_header((header::IF_NONE_MATCH, tag.to_string()));
+            }
+            req.to_request()
+        };
+        let etag_of = |res: &actix_web::dev::ServiceResponse| res.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+
+        // Lists carry weak tags, matched with or without W/
+        let list_etag = etag_of(&test::call_service(&app, get("/books", None)).await);
+        assert!(list_etag.starts_with("W/\""));
+        assert_eq!(test::call_service(&app, get("/books", Some(&list_etag))).await.status(), 304);
+        assert_eq!(test::call_service(&app, get("/books", Some(list_etag.trim_start_matches("W/")))).await.status(), 304);
+        assert_eq!(test::call_service(&app, get("/books?q=nothing", Some(&list_etag))).await.status(), 200);
+
+        // Single books carry strong tags; If-None-Match still compares weakly
+        let book_etag = etag_of(&test::call_service(&app, get("/books/1", None)).await);
+        assert_eq!(book_etag, "\"1\"");
+        assert_eq!(test::call_service(&app, get("/books/1", Some("W/\"1\""))).await.status(), 304);
+        assert_ne!(etag_of(&test::call_service(&app, get("/books/1?id_as_string=true", None)).await), book_etag);
+
+        // If-Match compares strongly, so a weak tag never matches
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((header::IF_MATCH, "W/\"1\"")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 412);
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((header::IF_MATCH, book_etag)).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_root_describes_the_api() {
+        let app = test::init_service(App::new().route("/", web::get().to(api_index))).await;
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["name"], "restapi-rust");
+        assert_eq!(body["links"]["books"], "/books");
+        assert_eq!(body["links"]["health"], "/health");
+    }
+
+    #[actix_web::test]
+    async fn test_json_export_is_an_attachment_and_filtered() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "Dune Messiah", "Frank Herbert"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books/export.json").route(web::get().to(export_books_json)))).await;
+
+        let req = test::TestRequest::get().uri("/books/export.json").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::CONTENT_DISPOSITION).unwrap(), "attachment; filename=\"books.json\"");
+        let books: Vec<Book> = test::read_body_json(res).await;
+        assert_eq!(books.len(), 3);
+
+        let req = test::TestRequest::get().uri("/books/export.json?q=dune&sort=id:desc").to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 1]);
+
+        let req = test::TestRequest::get().uri("/books/export.json?sort=publisher").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_parameter");
+    }
+
+    #[actix_web::test]
+    async fn test_import_applies_each_conflict_policy() {
+        let imported = serde_json::json!([
+            {"id": 1, "title": "Dune Messiah", "author": "Frank Herbert"},
+            {"id": 7, "title": "Emma", "author": "Jane Austen"}
+        ]);
+        for (policy, status, report, title) in [
+            ("skip", 200, Some(ImportReport { created: 1, overwritten: 0, skipped: 1 }), "Dune"),
+            ("overwrite", 200, Some(ImportReport { created: 1, overwritten: 1, skipped: 0 }), "Dune Messiah"),
+            ("error", 409, None, "Dune"),
+        ] {
+            let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+            let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+            .service(web::resource("/books/import").route(web::post().to(import_books)))).await;
+            let req = test::TestRequest::post().uri(&format!("/books/import?on_conflict={}", policy)).set_json(&imported).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), status, "{}", policy);
+            if let Some(report) = report {
+                assert_eq!(test::read_body_json::<ImportReport, _>(res).await, report, "{}", policy);
+            }
+            let store = store.read().await;
+            assert_eq!(store.books.iter().find(|b| b.id == 1).unwrap().title, title, "{}", policy);
+            assert_eq!(store.books.len(), if status == 200 { 2 } else { 1 }, "{}", policy);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_imported_books_are_stamped_and_versioned_by_the_store() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/import").route(web::post().to(import_books)))).await;
+        let since = now_millis();
+        let imported = serde_json::json!([
+            {"id": 1, "title": "Dune Messiah", "author": "Frank Herbert", "version": 5},
+            {"id": 7, "title": "Emma", "author": "Jane Austen", "version": 5}
+        ]);
+        let req = test::TestRequest::post().uri("/books/import?on_conflict=overwrite").set_json(&imported).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let store = store.read().await;
+        let versions: Vec<(i32, u32)> = store.books.iter().map(|b| (b.id, b.version)).collect();
+        assert_eq!(versions, vec![(1, 2), (7, 1)]);
+        assert!(store.books.iter().all(|b| b.updated_at >= since));
+    }
+
+    #[actix_web::test]
+    async fn test_import_counts_a_repeated_id_once_against_the_quota() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let config = Config { max_books: Some(2), ..Config::default() };
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/import").route(web::post().to(import_books)))).await;
+        let imported = serde_json::json!([
+            {"id": 7, "title": "Emma", "author": "Jane Austen"},
+            {"id": 7, "title": "Persuasion", "author": "Jane Austen"}
+        ]);
+        let req = test::TestRequest::post().uri("/books/import?on_conflict=overwrite").set_json(&imported).to_request();
+        let report: ImportReport = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(report, ImportReport { created: 1, overwritten: 1, skipped: 0 });
+        let store = store.read().await;
+        assert_eq!(store.books.len(), 2);
+        assert_eq!(store.books[1].title, "Persuasion");
+    }
+
+    #[actix_web::test]
+    async fn test_async_import_runs_as_a_job() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(Jobs::default())).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/import").route(web::post().to(import_books)))
+        .route("/admin/jobs/{id}", web::get().to(get_job))).await;
+        let import = |policy: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/books/import?async=true&on_conflict={}", policy))
+                .set_json(serde_json::json!([{"id": 1, "title": "Dune Messiah", "author": "Frank Herbert"}, {"id": 7, "title": "Emma", "author": "Jane Austen"}]))
+                .to_request()
+        };
+        let finished = |location: String| {
+            let app = &app;
+            async move {
+                for _ in 0..100 {
+                    let job: Job = test::call_and_read_body_json(app, test::TestRequest::get().uri(&location).to_request()).await;
+                    if job.is_finished() {
+                        return job;
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                panic!("job at {} did not finish", location);
+            }
+        };
+
+        let res = test::call_service(&app, import("overwrite")).await;
+        assert_eq!(res.status(), 202);
+        let location = res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+        let job: Job = test::read_body_json(res).await;
+        assert_eq!((job.status, job.total), (JobStatus::Pending, 2));
+        assert_eq!(location, format!("/admin/jobs/{}", job.id));
+        let job = finished(location).await;
+        assert_eq!(job.status, JobStatus::Done);
+        assert_eq!(job.report, Some(ImportReport { created: 1, overwritten: 1, skipped: 0 }));
+        assert_eq!(store.read().await.books.len(), 2);
+
+        // Conflicts surface on the job rather than the 202
+        let res = test::call_service(&app, import("error")).await;
+        assert_eq!(res.status(), 202);
+        let job = finished(res.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string()).await;
+        assert_eq!(job.status, JobStatus::Failed);
+        assert_eq!(job.error.as_deref(), Some("ids already exist: 1, 7"));
+
+        let req = test::TestRequest::get().uri("/admin/jobs/99").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_create_after_import_takes_an_unused_id() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/import").route(web::post().to(import_books)))).await;
+        let req = test::TestRequest::post().uri("/books/import").set_json(serde_json::json!([{"id": 5, "title": "Emma", "author": "Jane Austen"}])).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        let created: Book = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(created.id, 6);
+    }
+
+    #[actix_web::test]
+    async fn test_browse_pages_books_by_surname_initial() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "The Hobbit", "J. R. R. Tolkien"),
+            sample_book(2, "Anna Karenina", "Leo Tolstoy"),
+            sample_book(3, "Dune", "Frank Herbert"),
+            sample_book(4, "The Silmarillion", "J. R. R. Tolkien"),
+        ])));
+        let app = test::init_service(App::new().app_data(query_config()).app_data(web::Data::new(store))
+        .service(web::resource("/books/browse").route(web::get().to(browse_books)))).await;
+        let titles = |books: Vec<Book>| books.into_iter().map(|b| b.title).collect::<Vec<_>>();
+
+        let req = test::TestRequest::get().uri("/books/browse?letter=t").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(titles(test::read_body_json(res).await), ["The Hobbit", "The Silmarillion", "Anna Karenina"]);
+
+        let req = test::TestRequest::get().uri("/books/browse?letter=T&limit=2&offset=2").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().get(header::LINK).unwrap().to_str().unwrap().contains("rel=\"prev\""));
+        assert_eq!(titles(test::read_body_json(res).await), ["Anna Karenina"]);
+
+        let req = test::TestRequest::get().uri("/books/browse?letter=T&by=title").to_request();
+        assert_eq!(titles(test::call_and_read_body_json(&app, req).await), ["The Hobbit", "The Silmarillion"]);
+
+        let req = test::TestRequest::get().uri("/books/browse?letter=Q").to_request();
+        assert!(titles(test::call_and_read_body_json(&app, req).await).is_empty());
+
+        // Extractor failures, a missing letter or an unknown "by", share the envelope
+        for uri in ["/books/browse?letter=", "/books/browse?letter=T&limit=0", "/books/browse", "/books/browse?letter=T&by=isbn"] {
+            let res = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(res.status(), 400, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "invalid_parameter", "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_browse_index_lists_initials_with_books() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "The Hobbit", "J. R. R. Tolkien"),
+            sample_book(2, "Dune", "Frank Herbert"),
+            sample_book(3, "Emma", "jane austen"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/browse/index").route(web::get().to(browse_index)))).await;
+        let req = test::TestRequest::get().uri("/books/browse/index").to_request();
+        let initials: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(initials, ["A", "H", "T"]);
+        let req = test::TestRequest::get().uri("/books/browse/index?by=title").to_request();
+        let initials: Vec<String> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(initials, ["D", "E", "T"]);
+    }
+
+    #[actix_web::test]
+    async fn test_self_check_flags_bad_records() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let config = Config {
+            store_read_timeout_ms: 20,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/admin/selfcheck").route(web::get().to(self_check)))).await;
+        let req = test::TestRequest::get().uri("/admin/selfcheck").to_request();
+        let report: SelfCheckReport = test::call_and_read_body_json(&app, req).await;
+        assert!(report.violations.is_empty());
+
+        // Written past the store's methods, the way corruption would get in
+        store.write().await.books.push(sample_book(2, " ", "Nobody"));
+        let req = test::TestRequest::get().uri("/admin/selfcheck").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        let report: SelfCheckReport = test::read_body_json(res).await;
+        assert_eq!(report.books, 3);
+        assert_eq!(report.violations, [
+            Violation { id: 2, problem: "duplicate id".to_string() },
+            Violation { id: 2, problem: "empty title".to_string() },
+        ]);
+
+        let _writer = store.write().await;
+        let req = test::TestRequest::get().uri("/admin/selfcheck").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 500);
+    }
+
+    #[actix_web::test]
+    async fn test_update_into_a_duplicate_is_a_conflict() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::patch().to(batch_update_books)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+
+        let req = test::TestRequest::put().uri("/books/2").set_json(serde_json::json!({"title": "dune ", "author": "Frank  Herbert"})).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 409);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "conflict");
+        assert_eq!(store.read().await.books[1].title, "Emma");
+
+        let req = test::TestRequest::patch().uri("/books").set_json(serde_json::json!({"ids": [2], "changes": {"title": "Dune", "author": "Frank Herbert"}})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 409);
+        // Two books of one batch can't be given the same key either
+        let req = test::TestRequest::patch().uri("/books").set_json(serde_json::json!({"ids": [1, 2], "changes": {"title": "Solaris", "author": "Stanislaw Lem"}})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 409);
+        assert_eq!(store.read().await.books[1].title, "Emma");
+
+        // Renaming a book onto its own key is not a conflict
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_update_into_a_duplicate_is_allowed_when_configured() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let config = Config {
+            allow_duplicates: true,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+        let req = test::TestRequest::put().uri("/books/2").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert_eq!(store.read().await.books[1].title, "Dune");
+    }
+
+    #[actix_web::test]
+    async fn test_over_limit_is_rejected_unless_handling_is_lenient() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books?limit=1000000").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_parameter");
+        assert_eq!(body["error"]["message"], format!("limit must be at most {}", MAX_LIST_LIMIT));
+        let req = test::TestRequest::get().uri("/books?limit=1000000").insert_header(("Prefer", "handling=strict")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        let req = test::TestRequest::get().uri("/books?limit=1000000&envelope=true").insert_header(("Prefer", "handling=lenient")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get("Preference-Applied").unwrap(), "handling=lenient");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["data"].as_array().unwrap().len(), 2);
+        assert_eq!(body["pagination"]["limit"], MAX_LIST_LIMIT);
+
+        let req = test::TestRequest::get().uri("/books?limit=0").insert_header(("Prefer", "handling=lenient")).to_request();
+        let books: Vec<Book> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(books.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_count_by_author_orders_by_count_then_name() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Emma", "Jane Austen"),
+            sample_book(2, "Dune", "Frank Herbert"),
+            sample_book(3, "Persuasion", "jane austen"),
+            sample_book(4, "Ubik", "Philip K. Dick"),
+            sample_book(5, "Children of Dune", "Frank Herbert"),
+            sample_book(6, "Solaris", "Stanislaw Lem"),
+            sample_book(7, "Sense and Sensibility", "Jane Austen"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/count-by-author").route(web::get().to(count_books_by_author)))).await;
+        let count = |author: &str, count: usize| AuthorCount { author: author.to_string(), count };
+
+        let req = test::TestRequest::get().uri("/books/count-by-author").to_request();
+        let counts: Vec<AuthorCount> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(counts, [count("Jane Austen", 3), count("Frank Herbert", 2), count("Philip K. Dick", 1), count("Stanislaw Lem", 1)]);
+
+        let req = test::TestRequest::get().uri("/books/count-by-author?limit=2").to_request();
+        let counts: Vec<AuthorCount> = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(counts, [count("Jane Austen", 3), count("Frank Herbert", 2)]);
+    }
+
+    #[actix_web::test]
+    async fn test_get_book_embeds_links_in_hateoas_mode() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(3, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books/3?hateoas=true").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["title"], "Dune");
+        assert_eq!(body["_links"], serde_json::json!({
+            "self": {"href": "/books/3"},
+            "update": {"href": "/books/3", "method": "PUT"},
+            "delete": {"href": "/books/3", "method": "DELETE"},
+        }));
+
+        let req = test::TestRequest::get().uri("/books/3").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(body.get("_links").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_hateoas_etag_satisfies_if_match() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(3, "Dune", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)).route(web::delete().to(delete_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books/3?hateoas=true").to_request();
+        let res = test::call_service(&app, req).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        assert!(etag.to_str().unwrap().contains("-links"));
+        let req = test::TestRequest::delete().uri("/books/3").insert_header((header::IF_MATCH, etag)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_update_returns_only_changed_fields_on_request() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Emma", "Jane Austen")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::patch().to(batch_update_books)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+
+        let req = test::TestRequest::put().uri("/books/1?return=changed").set_json(serde_json::json!({"title": "Dune Messiah", "author": "Frank Herbert"})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"id": 1, "title": "Dune Messiah"}));
+
+        let req = test::TestRequest::patch().uri("/books?return=changed").set_json(serde_json::json!({"ids": [2, 9], "changes": {"author": "J. Austen"}})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body, serde_json::json!({"updated": [{"id": 2, "author": "J. Austen"}], "missing": [9]}));
+
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["author"], "Frank Herbert");
+        assert_eq!(body["version"], 3);
+    }
+
+    #[actix_web::test]
+    async fn test_incomplete_lists_books_missing_required_fields() {
+        let complete = Book {
+            genre: Some("Science Fiction".to_string()),
+            isbn: Some("978-0-441-17271-9".to_string()),
+            published_year: Some(1965),
+            ..sample_book(1, "Dune", "Frank Herbert")
+        };
+        let no_isbn = Book {
+            genre: Some("Romance".to_string()),
+            published_year: Some(1815),
+            ..sample_book(2, "Emma", "Jane Austen")
+        };
+        let no_genre = Book {
+            isbn: Some("0-15-602760-7".to_string()),
+            published_year: Some(1961),
+            ..sample_book(3, "Solaris", "Stanislaw Lem")
+        };
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![complete, no_isbn, no_genre])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/incomplete").route(web::get().to(get_incomplete_books)))).await;
+        let ids = |books: Vec<Book>| books.into_iter().map(|b| b.id).collect::<Vec<_>>();
+
+        let req = test::TestRequest::get().uri("/books/incomplete").to_request();
+        assert_eq!(ids(test::call_and_read_body_json(&app, req).await), [2, 3]);
+        let req = test::TestRequest::get().uri("/books/incomplete?require=isbn").to_request();
+        assert_eq!(ids(test::call_and_read_body_json(&app, req).await), [2]);
+        let req = test::TestRequest::get().uri("/books/incomplete?require=cover").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_parameter");
+    }
+
+    #[actix_web::test]
+    async fn test_create_rejects_invalid_isbn() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert", "isbn": "978-0-441-17271-8"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 422);
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert", "isbn": "978-0-441-17271-9", PUBLISHED_YEAR: 1965})).to_request();
+        let book: Book = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(book.isbn.as_deref(), Some("978-0-441-17271-9"));
+        assert_eq!(book.published_year, Some(1965));
+    }
+
+    #[actix_web::test]
+    async fn test_id_prefix_on_create_and_lookup() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(41, "Emma", "Jane Austen")])));
+        let config = Config {
+            id_prefix: Some("lib1".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/books/lib1-00042");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["id"], "lib1-00042");
+
+        let req = test::TestRequest::get().uri("/books/lib1-00042").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["title"], "Dune");
+
+        for id in ["lib2-00042", "42", "lib1-x"] {
+            let req = test::TestRequest::get().uri(&format!("/books/{}", id)).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 400, "{}", id);
+            let body: serde_json::Value = test::read_body_json(res).await;
+            assert_eq!(body["error"]["code"], "invalid_id");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_id_prefix_on_every_book_bearing_response() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(41, "Emma", "Jane Austen"), sample_book(43, "Emma", "Jane Austen")])));
+        let config = Config {
+            id_prefix: Some("lib1".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::patch().to(batch_update_books)))
+        .service(web::resource("/books/bulk").route(web::post().to(bulk_create_books)))
+        .service(web::resource("/books/recent").route(web::get().to(get_recent_books)))
+        .service(web::resource("/books/duplicates").route(web::get().to(get_duplicate_books)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::post().uri("/books/bulk").set_json(serde_json::json!([{"title": "Dune", "author": "Frank Herbert"}])).to_request();
+        let created: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(created[0]["id"], "lib1-00044");
+        // An id the service handed out works as a path segment
+        let req = test::TestRequest::get().uri(&format!("/books/{}", created[0]["id"].as_str().unwrap())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::get().uri("/books/recent").to_request();
+        let recent: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(recent[0]["id"], "lib1-00044");
+        let req = test::TestRequest::get().uri("/books/duplicates").to_request();
+        let groups: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(groups[0]["ids"], serde_json::json!(["lib1-00041", "lib1-00043"]));
+        let req = test::TestRequest::patch().uri("/books").set_json(serde_json::json!({"ids": [44, 99], "changes": {"author": "F. Herbert"}})).to_request();
+        let result: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(result["updated"][0]["id"], "lib1-00044");
+        assert_eq!(result["missing"], serde_json::json!(["lib1-00099"]));
+    }
+
+    #[actix_web::test]
+    async fn test_batch_json_patch_atomic_and_per_item() {
+        let app_for = |store: Books| {
+            test::init_service(App::new().app_data(web::Data::new(store)).service(
+                web::resource("/books")
+                    .route(web::patch().guard(actix_web::guard::Header("content-type", JSON_PATCH)).to(batch_patch_books))
+                    .route(web::patch().to(batch_update_books)),
+            ))
+        };
+        let patches = serde_json::json!([
+            {"id": 1, "patch": [{"op": "replace", "path": "/title", "value": "Persuasion"}, {"op": "add", "path": "/genre", "value": "novel"}]},
+            {"id": 2, "patch": [{"op": "test", "path": "/title", "value": "Not this one"}, {"op": "replace", "path": "/title", "value": "X"}]},
+        ]);
+        let request = |uri: &str| {
+            test::TestRequest::patch()
+                .uri(uri)
+                .insert_header((header::CONTENT_TYPE, JSON_PATCH))
+                .set_payload(patches.to_string())
+                .to_request()
+        };
+
+        // Without the flag the valid patch lands and the failing one is reported
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Emma", "Jane Austen"), sample_book(2, "Dune", "Frank Herbert")])));
+        let app = app_for(store.clone()).await;
+        let res = test::call_service(&app, request("/books")).await;
+        assert_eq!(res.status(), 200);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["results"][0]["status"], 200);
+        assert_eq!(body["results"][0]["book"]["title"], "Persuasion");
+        assert_eq!(body["results"][1]["status"], 422);
+        assert_eq!(body["results"][1]["error"]["code"], "validation_failed");
+        let titles: Vec<String> = store.read().await.books.iter().map(|b| b.title.clone()).collect();
+        assert_eq!(titles, ["Persuasion", "Dune"]);
+        assert_eq!(store.read().await.books[0].genre.as_deref(), Some("novel"));
+
+        // Atomically the failure undoes the valid patch too
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Emma", "Jane Austen"), sample_book(2, "Dune", "Frank Herbert")])));
+        let app = app_for(store.clone()).await;
+        let res = test::call_service(&app, request("/books?atomic=true")).await;
+        assert_eq!(res.status(), 422);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["results"][0]["status"], 424);
+        assert_eq!(body["results"][0]["error"]["code"], "not_applied");
+        assert_eq!(body["results"][1]["status"], 422);
+        let titles: Vec<String> = store.read().await.books.iter().map(|b| b.title.clone()).collect();
+        assert_eq!(titles, ["Emma", "Dune"]);
+        assert_eq!(store.read().await.books[0].version, 1);
+
+        let req = test::TestRequest::patch()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, JSON_PATCH))
+            .insert_header((SCHEMA_VERSION_HEADER, "99"))
+            .set_payload(patches.to_string())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_schema_version");
+    }
+
+    #[actix_web::test]
+    async fn test_log_level_change_enables_debug_records() {
+        // Records reaching the logger, as the log macros would hand them over
+        #[derive(Clone, Default)]
+        struct Captured(Arc<std::sync::Mutex<Vec<String>>>);
+        impl log::Log for Captured {
+            fn enabled(&self, _: &log::Metadata) -> bool {
+                true
+            }
+            fn log(&self, record: &log::Record) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+            fn flush(&self) {}
+        }
+        let captured = Captured::default();
+        let log_level = LogLevel::new(LevelFilter::Info);
+        let logger = crate::logging::ReloadableLogger::new(captured.clone(), log_level.clone());
+        let emit = |message: &str| log::Log::log(&logger, &log::Record::builder().level(log::Level::Debug).args(format_args!("{}", message)).build());
+        let app = test::init_service(App::new().app_data(web::Data::new(log_level)).route("/admin/log-level", web::post().to(set_log_level))).await;
+
+        emit("before");
+        let req = test::TestRequest::post().uri("/admin/log-level").set_json(serde_json::json!({"level": "DEBUG"})).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["level"], "debug");
+        emit("after");
+        assert_eq!(*captured.0.lock().unwrap(), ["after"]);
+
+        let req = test::TestRequest::post().uri("/admin/log-level").set_json(serde_json::json!({"level": "loud"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 422);
+        log::set_max_level(LevelFilter::Info);
+    }
+
+    #[actix_web::test]
+    async fn test_changes_feed_returns_books_changed_since() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Emma", "Jane Austen"), sample_book(2, "Dune", "Frank Herbert"), sample_book(3, "Ubik", "Philip K. Dick")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/changes").route(web::get().to(get_book_changes)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)).route(web::delete().to(delete_book)))).await;
+        let changes = |since: u64| test::TestRequest::get().uri(&format!("/books/changes?since={}", since)).to_request();
+
+        let since = now_millis();
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let req = test::TestRequest::put().uri("/books/2").set_json(serde_json::json!({"title": "Dune Messiah", "author": "Frank Herbert"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        // A change in the millisecond of a poll is sent again by the next one
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let res = test::call_service(&app, changes(since)).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        let feed: ChangesFeed = test::read_body_json(res).await;
+        assert_eq!(feed.updated.iter().map(|b| (b.id, b.title.as_str())).collect::<Vec<_>>(), [(2, "Dune Messiah")]);
+        assert!(feed.deleted.is_empty());
+
+        // Unchanged since the last poll
+        let req = test::TestRequest::get().uri(&format!("/books/changes?since={}", since)).insert_header((header::IF_NONE_MATCH, etag)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 304);
+
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        let req = test::TestRequest::delete().uri("/books/3").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let next: ChangesFeed = test::call_and_read_body_json(&app, changes(feed.until)).await;
+        assert!(next.updated.is_empty());
+        assert_eq!(next.deleted.iter().map(|t| t.id).collect::<Vec<_>>(), [3]);
+    }
+
+    #[actix_web::test]
+    async fn test_count_books_by_decade() {
+        let store: Books = Arc::new(RwLock::new(
+            [Some(1999), Some(1990), Some(2005), None, Some(1965), Some(2000), Some(1994)]
+                .iter()
+                .enumerate()
+                .map(|(i, year)| Book {
+                    published_year: *year,
+                    ..sample_book(i as i32 + 1, &format!("Title {}", i), "Author")
+                })
+                .collect(),
+        ));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books/by-decade").route(web::get().to(count_books_by_decade)))).await;
+
+        let req = test::TestRequest::get().uri("/books/by-decade").to_request();
+        let counts: Vec<DecadeCount> = test::call_and_read_body_json(&app, req).await;
+        let counts: Vec<(i32, usize)> = counts.iter().map(|c| (c.decade, c.count)).collect();
+        assert_eq!(counts, [(1960, 1), (1990, 3), (2000, 2)]);
+    }
+
+    #[actix_web::test]
+    async fn test_genre_allowlist_rejects_off_list_genres() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert")])));
+        let config = Config {
+            genre_allowlist: vec!["Science Fiction".to_string(), "Fantasy".to_string()],
+            ..Config::default()
+        };
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
+
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Emma", "author": "Jane Austen", "genre": "Romance"})).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 422);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["message"], "genre \"Romance\" is not one of: Science Fiction, Fantasy");
+
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "The Hobbit", "author": "J. R. R. Tolkien", "genre": "fantasy"})).to_request();
+        let created: Book = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(created.genre.as_deref(), Some("fantasy"));
+
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert", "genre": "Cooking"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 422);
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert", "genre": "Science Fiction"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        // Leaving the genre out keeps it
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        assert_eq!(store.read().await.books[0].genre.as_deref(), Some("Science Fiction"));
+    }
+
+    #[actix_web::test]
+    async fn test_get_book_embeds_the_authors_other_books() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![
+            sample_book(1, "Dune", "Frank Herbert"),
+            sample_book(2, "Emma", "Jane Austen"),
+            sample_book(3, "Dune Messiah", "frank herbert"),
+            sample_book(4, "Children of Dune", "Frank Herbert"),
+        ])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store)).service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+        let siblings = |body: &serde_json::Value| body["author_books"].as_array().unwrap().iter().map(|b| b["id"].as_i64().unwrap()).collect::<Vec<_>>();
+
+        let req = test::TestRequest::get().uri("/books/1?include=author_books").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["title"], "Dune");
+        assert_eq!(siblings(&body), [3, 4]);
+
+        let req = test::TestRequest::get().uri("/books/4?include=author_books&author_books_limit=1").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(siblings(&body), [1]);
+
+        let req = test::TestRequest::get().uri("/books/2?include=author_books").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(siblings(&body), Vec::<i64>::new());
+
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(body.get("author_books").is_none());
+        let req = test::TestRequest::get().uri("/books/1?include=reviews").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_parameter");
+    }
+
+    #[actix_web::test]
+    async fn test_author_books_etag_satisfies_if_match() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Dune", "Frank Herbert"), sample_book(2, "Dune Messiah", "Frank Herbert")])));
+        let app = test::init_service(App::new().app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)).route(web::delete().to(delete_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books/1?include=author_books").to_request();
+        let res = test::call_service(&app, req).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        assert!(etag.to_str().unwrap().contains("-authors"));
+        let req = test::TestRequest::delete().uri("/books/1").insert_header((header::IF_MATCH, etag)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_seeded_random_book_is_repeatable() {
+        let store: Books = Arc::new(RwLock::new((1..=20).map(|id| sample_book(id, &format!("Title {}", id), "Author")).collect()));
+        let picks = |seed: u64| {
+            let store = store.clone();
+            async move {
+                let app = test::init_service(App::new().app_data(web::Data::new(Rng::new(Some(seed)))).app_data(web::Data::new(store))
+                .service(web::resource("/books/random").route(web::get().to(get_random_book)))).await;
+                let mut ids = Vec::new();
+                for _ in 0..5 {
+                    let book: Book = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/books/random").to_request()).await;
+                    ids.push(book.id);
+                }
+                ids
+            }
+        };
+        assert_eq!(picks(42).await, [14, 12, 19, 5, 11]);
+        assert_eq!(picks(42).await, picks(42).await);
+        assert_ne!(picks(42).await, picks(7).await);
+
+        let empty: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().app_data(web::Data::new(empty)).service(web::resource("/books/random").route(web::get().to(get_random_book)))).await;
+        assert_eq!(test::call_service(&app, test::TestRequest::get().uri("/books/random").to_request()).await.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_validate_isbns_reports_each_entry() {
+        let app = test::init_service(App::new().app_data(json_config()).service(web::resource("/books/validate-isbns").route(web::post().to(validate_isbns)))).await;
+        let req = test::TestRequest::post()
+            .uri("/books/validate-isbns")
+            .set_json(["0-15-602760-7", "080442957x", "978-0-306-40615-7", "978-0-306-40615-8", "12345", ""])
+            .to_request();
+        let checks: Vec<IsbnCheck> = test::call_and_read_body_json(&app, req).await;
+        let results: Vec<(&str, bool, Option<&str>)> = checks.iter().map(|c| (c.isbn.as_str(), c.valid, c.normalized.as_deref())).collect();
+        assert_eq!(
+            results,
+            [
+                ("0-15-602760-7", true, Some("0156027607")),
+                ("080442957x", true, Some("080442957X")),
+                ("978-0-306-40615-7", true, Some("9780306406157")),
+                ("978-0-306-40615-8", false, None),
+                ("12345", false, None),
+                ("", false, None),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_max_books_caps_creates_but_not_updates_or_deletes() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Emma", "Jane Austen")])));
+        let config = Config { max_books: Some(2), ..Config::default() };
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::resource("/books/bulk").route(web::post().to(bulk_create_books)))
+        .service(web::resource("/books/{id}").route(web::put().to(update_book)).route(web::delete().to(delete_book)))).await;
+
+        // A batch that would overshoot is rejected whole
+        let req = test::TestRequest::post()
+            .uri("/books/bulk")
+            .set_json(serde_json::json!([{"title": "Dune", "author": "Frank Herbert"}, {"title": "Ubik", "author": "Philip K. Dick"}]))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 403);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "quota_exceeded");
+        assert_eq!(store.read().await.books.len(), 1);
+
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Ubik", "author": "Philip K. Dick"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Persuasion", "author": "Jane Austen"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::delete().uri("/books/1").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Ubik", "author": "Philip K. Dick"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn test_book_ids_match_the_filtered_list() {
+        let mut dune = sample_book(1, "Dune", "Frank Herbert");
+        dune.tags = vec!["scifi".to_string()];
+        let mut ubik = sample_book(3, "Ubik", "Philip K. Dick");
+        ubik.tags = vec!["SciFi".to_string()];
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![dune, sample_book(2, "Emma", "Jane Austen"), ubik])));
+        let app = test::init_service(App::new().app_data(web::Data::new(Config::default())).app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/ids").route(web::get().to(get_book_ids)))).await;
+
+        let req = test::TestRequest::get().uri("/books?tag=scifi&sort=title:desc").to_request();
+        let listed: Vec<Book> = test::read_body_json(test::call_service(&app, req).await).await;
+        let req = test::TestRequest::get().uri("/books/ids?tag=scifi&sort=title:desc").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        let ids: Vec<i32> = test::read_body_json(res).await;
+        assert_eq!(ids, listed.iter().map(|b| b.id).collect::<Vec<_>>());
+        assert_eq!(ids, vec![3, 1]);
+
+        let req = test::TestRequest::get().uri("/books/ids?q=austen").to_request();
+        let ids: Vec<i32> = test::read_body_json(test::call_service(&app, req).await).await;
+        assert_eq!(ids, vec![2]);
+
+        let req = test::TestRequest::get().uri("/books/ids?sort=title:sideways").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_parameter");
+    }
+
+    #[actix_web::test]
+    async fn test_book_cache_serves_hits_without_the_store_and_drops_written_books() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![sample_book(1, "Emma", "Jane Austen"), sample_book(2, "Dune", "Frank Herbert")])));
+        let config = Config {
+            store_read_timeout_ms: 20,
+            ..Config::default()
+        };
+        let cache = web::Data::new(BookCache::new(16));
+        let app = test::init_service(App::new().app_data(json_config()).app_data(web::Data::new(config)).app_data(cache.clone()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)).route(web::put().to(update_book)).route(web::delete().to(delete_book)))).await;
+        let title = |res: actix_web::dev::ServiceResponse| async move { test::read_body_json::<Book, _>(res).await.title };
+
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        assert_eq!(title(test::call_service(&app, req).await).await, "Emma");
+        // With the store locked by a writer, only a cache hit can answer before the read timeout
+        let writer = store.write().await;
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(title(res).await, "Emma");
+        assert_eq!(cache.hits(), 1);
+        drop(writer);
+
+        let req = test::TestRequest::put().uri("/books/1").set_json(serde_json::json!({"title": "Persuasion", "author": "Jane Austen"})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        assert_eq!(title(test::call_service(&app, req).await).await, "Persuasion");
+
+        let req = test::TestRequest::delete().uri("/books/1").to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+        let req = test::TestRequest::get().uri("/books/1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+}
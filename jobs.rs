@@ -0,0 +1,60 @@
+use crate::models::{ImportReport, Job, JobStatus};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+// Finished jobs kept for polling; older ones are forgotten first
+const MAX_FINISHED_JOBS: usize = 100;
+
+// Background jobs started by endpoints such as POST /books/import?async=true, registered as app
+// data and polled through GET /admin/jobs/{id}
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+}
+
+impl Jobs {
+    // Register a pending job over `total` items, returning it as first reported
+    pub fn start(&self, total: usize) -> Job {
+        let job = Job {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            status: JobStatus::Pending,
+            total,
+            report: None,
+            error: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        let finished: Vec<u64> = jobs.values().filter(|job| job.is_finished()).map(|job| job.id).collect();
+        for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_JOBS - 1)) {
+            jobs.remove(id);
+        }
+        jobs.insert(job.id, job.clone());
+        job
+    }
+
+    pub fn set_running(&self, id: u64) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            job.status = JobStatus::Running;
+        }
+    }
+
+    pub fn finish(&self, id: u64, result: Result<ImportReport, String>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            match result {
+                Ok(report) => {
+                    job.status = JobStatus::Done;
+                    job.report = Some(report);
+                }
+                Err(message) => {
+                    job.status = JobStatus::Failed;
+                    job.error = Some(message);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+}
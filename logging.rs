@@ -0,0 +1,77 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+// Shared log level, changeable at runtime through POST /admin/log-level; it governs records of
+// the log crate only, not tracing spans
+#[derive(Clone)]
+pub struct LogLevel {
+    level: Arc<AtomicUsize>,
+}
+
+impl LogLevel {
+    pub fn new(level: LevelFilter) -> Self {
+        LogLevel {
+            level: Arc::new(AtomicUsize::new(level as usize)),
+        }
+    }
+
+    pub fn get(&self) -> LevelFilter {
+        LEVELS[self.level.load(Ordering::SeqCst)]
+    }
+
+    // Also moves the max level of the log crate, whose macros drop records above it before
+    // any logger sees them
+    pub fn set(&self, level: LevelFilter) {
+        self.level.store(level as usize, Ordering::SeqCst);
+        log::set_max_level(level);
+    }
+}
+
+// Logger passing records within the runtime level on to another logger
+pub struct ReloadableLogger<L> {
+    inner: L,
+    level: LogLevel,
+}
+
+impl<L> ReloadableLogger<L> {
+    pub fn new(inner: L, level: LogLevel) -> Self {
+        ReloadableLogger { inner, level }
+    }
+}
+
+impl<L: Log> Log for ReloadableLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level.get() && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Install env_logger behind a runtime level starting at the one RUST_LOG sets. Module
+// directives in RUST_LOG still apply, but can only quiet a module below the runtime level
+pub fn init() -> LogLevel {
+    let initial = env_logger::Builder::from_default_env().build().filter();
+    let inner = env_logger::Builder::from_default_env().filter_level(LevelFilter::Trace).build();
+    let level = LogLevel::new(initial);
+    log::set_boxed_logger(Box::new(ReloadableLogger::new(inner, level.clone()))).expect("logger installed twice");
+    log::set_max_level(initial);
+    level
+}
@@ -1,201 +1,133 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use actix_web::middleware::Logger;
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use std::sync::Arc;
-use log::info;
-use lazy_static::lazy_static;
-
-// Define a struct to represent a book
-#[derive(Serialize, Deserialize, Clone)]
-struct Book {
-    id: i32,
-    title: String,
-    author: String,
-}
-
-// Define a struct to represent a new book
-#[derive(Serialize, Deserialize)]
-struct NewBook {
-    title: String,
-    author: String,
-}
-
-// In-memory storage for books
-type Books = Arc<RwLock<Vec<Book>>>;
-
-lazy_static! {
-    static ref BOOKS: Books = Arc::new(RwLock::new(vec![]));
-}
-
-
-// Endpoint to get all books
-async fn get_books(books: web::Data<Books>) -> impl Responder {
-            info!("get all books");
-    let books = books.read().await;
-    let books = books.clone();
-    HttpResponse::Ok().json(books)
-}
-
-// Endpoint to get a book by id
-async fn get_book(id: web::Path<i32>, books: web::Data<Books>) -> impl Responder {
-        info!("get book");
-    let books = books.read().await;
-    let book = books.iter().find(|b| b.id == *id);
-    match book {
-        Some(book) => HttpResponse::Ok().json(book),
-        None => HttpResponse::NotFound().body("Book not found"),
-    }
-}
-
-// Endpoint to create a new book
-async fn create_book(new_book: web::Json<NewBook>, books: web::Data<Books>) -> impl Responder {
-    info!("create book");
-    let mut books = books.write().await;
-    let id = books.len() as i32 + 1;
-    let book = Book {
-        id,
-        title: new_book.title.clone(),
-        author: new_book.author.clone(),
-    };
-    books.push(book.clone());
-    HttpResponse::Created().json(book)
-}
-
-// Endpoint to update a book
-async fn update_book(id: web::Path<i32>, new_book: web::Json<NewBook>, books: web::Data<Books>) -> impl Responder {
-    info!("update book");
-    let mut books = books.write().await;
-    let book = books.iter_mut().find(|b| b.id == *id);
-    match book {
-        Some(book) => {
-            book.title = new_book.title.clone();
-            book.author = new_book.author.clone();
-            HttpResponse::Ok().json(book)
-        }
-        None => HttpResponse::NotFound().body("Book not found"),
-    }
-}
-
-// Endpoint to delete a book
-async fn delete_book(id: web::Path<i32>, books: web::Data<Books>) -> impl Responder {
-    info!("delete books");
-    let mut books = books.write().await;
-    let index = books.iter().position(|b| b.id == *id);
-    match index {
-        Some(index) => {
-            books.remove(index);
-            HttpResponse::Ok().body("Book deleted")
+mod breaker;
+mod cache;
+mod config;
+mod errors;
+mod handlers;
+mod jobs;
+mod logging;
+mod middleware;
+mod models;
+mod patch;
+mod persist;
+mod random;
+mod routes;
+mod search;
+mod singleflight;
+mod store;
+mod tls;
+mod xml;
+
+use actix_web::middleware::{from_fn, Condition};
+use actix_web::{web, App, HttpServer};
+use breaker::CircuitBreaker;
+use config::Config;
+use handlers::{BookCache, ListCache, ListFlights};
+use jobs::Jobs;
+use random::Rng;
+use log::{info, warn};
+use middleware::{access_logger, authenticate, body_encoding, cache_control, compress, content_negotiation, cors, maintenance_guard, normalize_path, problem_details, rate_limit, require_tenant, security_headers, slow_request_log, trace_context, Maintenance, RateLimiter};
+use std::time::{Duration, Instant};
+use store::BOOKS;
+
+// Wait for SIGINT, or SIGTERM on unix platforms
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
         }
-        None => HttpResponse::NotFound().body("Book not found"),
     }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
-    info!("Server started on port 8080");
-    HttpServer::new(move || {
+    let log_level = logging::init();
+    let config = Config::from_env().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    if let Some(path) = &config.data_file {
+        *BOOKS.write().await = persist::load(path)?;
+        persist::spawn_flusher(path.clone(), BOOKS.clone(), Duration::from_secs(config.flush_interval_secs));
+    }
+    let tls_config = match config.tls_paths() {
+        Some((cert, key)) => Some(tls::load_server_config(cert, key).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?),
+        None => None,
+    };
+    info!("Server started on port 8080{}", if tls_config.is_some() { " with TLS" } else { "" });
+    let app_config = config.clone();
+    let maintenance = Maintenance::new(config.maintenance_mode);
+    let list_flights = web::Data::new(ListFlights::default());
+    let jobs = web::Data::new(Jobs::default());
+    let rng = web::Data::new(Rng::new(config.random_seed));
+    let list_cache = web::Data::new(ListCache::new(config.list_cache_entries));
+    let book_cache = web::Data::new(BookCache::new(config.book_cache_capacity));
+    let store_breaker = web::Data::new(CircuitBreaker::new(
+        config.store_breaker_threshold,
+        Duration::from_secs(config.store_breaker_cooldown_secs),
+    ));
+    let timeouts = config.server_timeouts();
+    let rate_limiter = web::Data::new(RateLimiter::new(config.rate_limit_per_minute, Duration::from_secs(60)));
+    let server = HttpServer::new(move || {
         App::new()
-            .wrap(Logger::default())
+            .wrap(from_fn(body_encoding))
+            .wrap(from_fn(require_tenant))
+            .wrap(from_fn(authenticate))
+            .wrap(from_fn(maintenance_guard))
+            .wrap(from_fn(rate_limit))
+            // Outside every middleware that rejects requests, so their errors are rendered too
+            .wrap(from_fn(problem_details))
+            .wrap(from_fn(content_negotiation))
+            .wrap(from_fn(compress))
+            .wrap(Condition::new(!app_config.cors_allowed_origins.is_empty(), cors(&app_config)))
+            .wrap(from_fn(cache_control))
+            .wrap(from_fn(security_headers))
+            .wrap(from_fn(slow_request_log))
+            .wrap(from_fn(trace_context))
+            .wrap(normalize_path(&app_config))
+            .wrap(access_logger(&app_config))
+            .app_data(web::Data::new(app_config.clone()))
+            .app_data(web::Data::new(maintenance.clone()))
+            .app_data(web::Data::new(log_level.clone()))
+            .app_data(rate_limiter.clone())
+            .app_data(list_flights.clone())
+            .app_data(jobs.clone())
+            .app_data(rng.clone())
+            .app_data(list_cache.clone())
+            .app_data(book_cache.clone())
+            .app_data(store_breaker.clone())
             .app_data(web::Data::new(BOOKS.clone()))
-            .service(
-                web::resource("/books")
-                    .route(web::get().to(get_books))
-                    .route(web::post().to(create_book)),
-            )
-            .service(
-                web::resource("/books/{id}")
-                    .route(web::get().to(get_book))
-                    .route(web::put().to(update_book))
-                    .route(web::delete().to(delete_book)),
-            )
+            .configure(routes::configure)
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
-}
-
-
-
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use actix_web::test;
-
-    #[actix_web::test]
-    async fn test_get_books() {
-        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books").route(web::get().to(get_books)))).await;
-        let req = test::TestRequest::get().uri("/books").to_request();
-        let res = test::call_service(&app, req).await;
-        assert_eq!(res.status(), 200);
-    }
-
-    #[actix_web::test]
-    async fn test_create_book() {
-        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books").route(web::post().to(create_book)))).await;
-        let req = test::TestRequest::post()
-            .uri("/books")
-            .set_json(&NewBook {
-                title: "Book Title".to_string(),
-                author: "Book Author".to_string(),
-            })
-            .to_request();
-        let res = test::call_service(&app, req).await;
-        assert_eq!(res.status(), 201);
-    }
-
-    #[actix_web::test]
-    async fn test_get_book() {
-        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone()))
-        .service(web::resource("/books").route(web::post().to(create_book)))
-        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
-        
-        let req = test::TestRequest::post()
-            .uri("/books")
-            .set_json(&NewBook {
-                title: "Book Title".to_string(),
-                author: "Book Author".to_string(),
-            })
-            .to_request();
-
-        let _res = test::call_service(&app, req).await;
-
-        let req = test::TestRequest::get().uri("/books/1").to_request();
-        let res = test::call_service(&app, req).await;
-        assert_eq!(res.status(), 200);
+    .shutdown_timeout(config.shutdown_timeout_secs)
+    .keep_alive(timeouts.keep_alive)
+    .client_request_timeout(timeouts.client_request_timeout)
+    .client_disconnect_timeout(timeouts.client_disconnect_timeout)
+    .disable_signals();
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23("127.0.0.1:8080", tls_config)?,
+        None => server.bind("127.0.0.1:8080")?,
     }
+    .run();
+
+    // Drive graceful shutdown ourselves so a timed-out drain can be reported
+    let handle = server.handle();
+    let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+    actix_web::rt::spawn(async move {
+        wait_for_shutdown_signal().await;
+        info!("shutting down, waiting up to {}s for in-flight requests", shutdown_timeout.as_secs());
+        let started = Instant::now();
+        handle.stop(true).await;
+        if started.elapsed() >= shutdown_timeout {
+            warn!("shutdown timeout of {}s elapsed, remaining connections were force-closed", shutdown_timeout.as_secs());
+        }
+    });
 
-    #[actix_web::test]
-    async fn test_update_book() {
-        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books/{id}").route(web::put().to(update_book)))).await;
-        let req = test::TestRequest::put()
-            .uri("/books/1")
-            .set_json(&NewBook {
-                title: "Updated Book Title".to_string(),
-                author: "Updated Book Author".to_string(),
-            })
-            .to_request();
-        let res = test::call_service(&app, req).await;
-        assert_eq!(res.status(), 200);
-    }
-
-    #[actix_web::test]
-    async fn test_delete_book() {
-        let app = test::init_service(App::new().app_data(web::Data::new(BOOKS.clone())).service(web::resource("/books/{id}").route(web::delete().to(delete_book)))).await;
-
-        let req = test::TestRequest::post()
-            .uri("/books")
-            .set_json(&NewBook {
-                title: "Book Title".to_string(),
-                author: "Book Author".to_string(),
-            })
-            .to_request();
-        let _res = test::call_service(&app, req).await;
-
-        let req = test::TestRequest::delete().uri("/books/1").to_request();
-        let res = test::call_service(&app, req).await;
-        assert_eq!(res.status(), 200);
+    server.await?;
+    if let Some(path) = &config.data_file {
+        persist::flush(path, &BOOKS).await?;
     }
+    Ok(())
 }
@@ -0,0 +1,1243 @@
+use crate::config::{Config, ResponseFormat, TrailingSlashMode};
+use crate::errors::{ApiError, ErrorDetails, MAX_JSON_BODY_BYTES, PROBLEM_JSON};
+use crate::models::Book;
+use crate::store::Store;
+use actix_cors::Cors;
+use actix_http::encoding::{Decoder, Encoder};
+use actix_web::body::{self, BodySize, BodyStream, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::HttpMessage;
+use actix_web::http::header::{AcceptEncoding, Encoding, HeaderName, HeaderValue};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::middleware::{Condition, Logger, Next, NormalizePath, TrailingSlash};
+use actix_web::{mime, web, Error, FromRequest, HttpRequest, HttpResponse};
+use log::warn;
+use tracing::{info_span, Instrument};
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Seconds clients are asked to wait before retrying a write during maintenance
+pub const MAINTENANCE_RETRY_AFTER_SECS: u64 = 60;
+
+// Shared maintenance mode flag, flippable at runtime
+#[derive(Clone, Default)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Self {
+        Maintenance {
+            enabled: Arc::new(AtomicBool::new(enabled)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+}
+
+// Middleware rejecting mutating requests with 503 while maintenance mode is on
+pub async fn maintenance_guard(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // Admin endpoints stay writable so maintenance can be turned off again
+    let read_only = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) || req.path().starts_with("/admin/");
+    let enabled = req.app_data::<web::Data<Maintenance>>().is_some_and(|m| m.is_enabled());
+    if enabled && !read_only {
+        return Ok(req.error_response(ApiError::Maintenance).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// Header carrying the API key for admin endpoints
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+// Middleware rejecting requests that do not carry the configured admin API key
+pub async fn require_api_key(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let expected = req.app_data::<web::Data<Config>>().and_then(|config| config.admin_api_key.clone());
+    let provided = req.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+    if expected.is_none() || provided != expected.as_deref() {
+        return Ok(req.error_response(ApiError::Unauthorized).map_into_right_body());
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// Header echoing the request's trace id back to the client
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+// W3C Trace Context of a request, attached to the request extensions by `trace_context`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    // 32 lowercase hex digits shared by every span of the trace
+    pub trace_id: String,
+    // Span id of the caller, None when this request started the trace
+    pub parent_id: Option<String>,
+}
+
+impl TraceContext {
+    // Parse a `traceparent` header ("00-<trace id>-<parent id>-<flags>"); ids that are
+    // malformed or all zeros are invalid, and so is the reserved version ff
+    pub fn parse(header: &str) -> Option<TraceContext> {
+        let mut parts = header.trim().split('-');
+        let (version, trace_id, parent_id, flags) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let hex = |value: &str, len: usize| value.len() == len && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+        let zero = |value: &str| value.bytes().all(|b| b == b'0');
+        // Later versions may append fields, version 00 may not
+        if !hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) || !hex(flags, 2) {
+            return None;
+        }
+        if !hex(trace_id, 32) || zero(trace_id) || !hex(parent_id, 16) || zero(parent_id) {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id: trace_id.to_string(),
+            parent_id: Some(parent_id.to_string()),
+        })
+    }
+
+    // Start a new trace with a random id
+    pub fn new_trace() -> TraceContext {
+        // RandomState is seeded randomly per instance, which is enough for ids that only need to be unique
+        let random = || RandomState::new().hash_one(Instant::now());
+        TraceContext {
+            trace_id: format!("{:016x}{:016x}", random(), random()),
+            parent_id: None,
+        }
+    }
+}
+
+// Middleware continuing the trace of an incoming `traceparent` header, or starting one, and
+// running the request in a span carrying the trace id, which X-Request-Id echoes back
+pub async fn trace_context(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let trace = req
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok())
+        .and_then(TraceContext::parse)
+        .unwrap_or_else(TraceContext::new_trace);
+    let span = info_span!("request", trace_id = %trace.trace_id, parent_id = trace.parent_id.as_deref(), method = %req.method(), path = req.path());
+    req.extensions_mut().insert(trace.clone());
+    let mut res = next.call(req).instrument(span).await?;
+    if let (Ok(name), Ok(value)) = (HeaderName::try_from(REQUEST_ID_HEADER), HeaderValue::from_str(&trace.trace_id)) {
+        res.headers_mut().insert(name, value);
+    }
+    Ok(res)
+}
+
+// Middleware warning about requests slower than the configured threshold
+pub async fn slow_request_log(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let threshold = req
+        .app_data::<web::Data<Config>>()
+        .map_or(Config::default().slow_request_ms, |config| config.slow_request_ms);
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    let trace_id = req.extensions().get::<TraceContext>().map(|trace| trace.trace_id.clone());
+    let started = Instant::now();
+    let res = next.call(req).await;
+    let elapsed = started.elapsed();
+    if elapsed > Duration::from_millis(threshold) {
+        match trace_id {
+            Some(trace_id) => warn!("slow request: {} {} took {}ms trace_id={}", method, path, elapsed.as_millis(), trace_id),
+            None => warn!("slow request: {} {} took {}ms", method, path, elapsed.as_millis()),
+        }
+    }
+    res
+}
+
+// Authenticated caller attached to the request extensions by `authenticate`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Principal {
+    pub name: String,
+    pub admin: bool,
+}
+
+impl Principal {
+    // Whether this caller may see a book; admins see everything
+    pub fn can_see(&self, owner: Option<&str>) -> bool {
+        self.admin || owner == Some(self.name.as_str())
+    }
+}
+
+// Middleware resolving X-Api-Key to a Principal. Anonymous requests pass through unless
+// per-caller API_KEYS are configured, in which case a known key is required
+pub async fn authenticate(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let config = req.app_data::<web::Data<Config>>().cloned();
+    let provided = req.headers().get(API_KEY_HEADER).and_then(|value| value.to_str().ok());
+    let principal = match (&config, provided) {
+        (Some(config), Some(key)) if config.admin_api_key.as_deref() == Some(key) => Some(Principal {
+            name: "admin".to_string(),
+            admin: true,
+        }),
+        (Some(config), Some(key)) => config.api_keys.get(key).map(|name| Principal {
+            name: name.clone(),
+            admin: false,
+        }),
+        _ => None,
+    };
+    let required = config.is_some_and(|config| !config.api_keys.is_empty());
+    // /admin has its own check, which keeps working when per-caller keys are disabled
+    if principal.is_none() && required && !req.path().starts_with("/admin/") {
+        return Ok(req.error_response(ApiError::Unauthorized).map_into_right_body());
+    }
+    if let Some(principal) = principal {
+        req.extensions_mut().insert(principal);
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// Header naming the tenant a request acts for
+pub const TENANT_HEADER: &str = "X-Tenant-Id";
+
+// Tenant attached to the request extensions by `require_tenant`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tenant(pub String);
+
+// Principal and tenant attached by `authenticate` and `require_tenant`, taken by handlers as
+// `ctx: AuthContext`. Extraction fails with 401 when API_KEYS require a principal and none was set
+#[derive(Clone, Debug, Default)]
+pub struct AuthContext {
+    pub principal: Option<Principal>,
+    pub tenant: Option<Tenant>,
+}
+
+impl AuthContext {
+    // Whether the caller may see a book; without a principal or tenant that check is off
+    pub fn can_see(&self, book: &Book) -> bool {
+        self.principal.as_ref().is_none_or(|principal| principal.can_see(book.owner.as_deref()))
+            && self.tenant.as_ref().is_none_or(|tenant| book.tenant_id.as_deref() == Some(tenant.0.as_str()))
+    }
+
+    // Look up a book the caller may see; others are reported missing rather than forbidden so ids don't leak
+    pub fn find<'a>(&self, store: &'a Store, id: i32) -> Option<&'a Book> {
+        store.books.iter().find(|b| b.id == id && self.can_see(b))
+    }
+}
+
+impl FromRequest for AuthContext {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let extensions = req.extensions();
+        let ctx = AuthContext {
+            principal: extensions.get::<Principal>().cloned(),
+            tenant: extensions.get::<Tenant>().cloned(),
+        };
+        let required = req.app_data::<web::Data<Config>>().is_some_and(|config| !config.api_keys.is_empty());
+        if ctx.principal.is_none() && required {
+            return ready(Err(ApiError::Unauthorized.into()));
+        }
+        ready(Ok(ctx))
+    }
+}
+
+// Middleware requiring X-Tenant-Id when tenant isolation is enabled; /admin spans all tenants
+pub async fn require_tenant(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let enabled = req.app_data::<web::Data<Config>>().is_some_and(|config| config.tenant_isolation);
+    if !enabled || req.path().starts_with("/admin/") {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let tenant = req
+        .headers()
+        .get(TENANT_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|tenant| !tenant.is_empty())
+        .map(str::to_string);
+    match tenant {
+        Some(tenant) => {
+            req.extensions_mut().insert(Tenant(tenant));
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        }
+        None => Ok(req.error_response(ApiError::MissingTenant).map_into_right_body()),
+    }
+}
+
+// Encodings offered to clients, in server preference order
+static COMPRESS_ENCODINGS: &[Encoding] = &[Encoding::brotli(), Encoding::gzip(), Encoding::deflate(), Encoding::zstd()];
+
+// Middleware compressing responses per Accept-Encoding, leaving bodies under
+// compress_min_bytes alone since compressing them costs more than it saves
+pub async fn compress(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let min_bytes = req
+        .app_data::<web::Data<Config>>()
+        .map_or(Config::default().compress_min_bytes, |config| config.compress_min_bytes);
+    let encoding = req.get_header::<AcceptEncoding>().and_then(|accept| accept.negotiate(COMPRESS_ENCODINGS.iter()));
+    let res = next.call(req).await?;
+    let small = matches!(res.response().body().size(), BodySize::Sized(size) if size < min_bytes);
+    match encoding {
+        Some(Encoding::Known(encoding)) if !small => Ok(res.map_body(move |head, body| Encoder::response(encoding, head, body)).map_into_left_body()),
+        _ => Ok(res.map_into_right_body()),
+    }
+}
+
+// Middleware rendering ApiError responses as RFC 7807 Problem Details when Accept lists
+// application/problem+json; other responses, and other clients, keep the usual error envelope
+pub async fn problem_details(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let wanted = req
+        .headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| media.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case(PROBLEM_JSON));
+    let res = next.call(req).await?;
+    let details = res.response().extensions().get::<ErrorDetails>().cloned();
+    let Some(details) = details.filter(|_| wanted) else {
+        return Ok(res.map_into_left_body());
+    };
+    let status = res.status();
+    let mut problem = HttpResponse::build(status);
+    for (name, value) in res.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            problem.append_header((name.clone(), value.clone()));
+        }
+    }
+    let body = details.to_problem(status, res.request().path());
+    let problem = problem.content_type(PROBLEM_JSON).body(body.to_string());
+    Ok(res.into_response(problem).map_into_right_body())
+}
+
+// Format a request's Accept header asks for, honouring q-values: the format with the higher
+// quality wins, then the one named exactly rather than through a wildcard. The configured
+// default applies when the header is absent or both formats tie, named or through wildcards;
+// JSON when nothing supported is acceptable
+pub fn negotiate_format(req: &ServiceRequest, default: ResponseFormat) -> ResponseFormat {
+    let ranges: Vec<(String, f32)> = req
+        .headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| {
+            let mut parts = range.split(';');
+            let media = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let q = parts.find_map(|param| param.trim().strip_prefix("q=")).and_then(|q| q.trim().parse().ok()).unwrap_or(1.0);
+            (media, q)
+        })
+        .filter(|(media, _)| !media.is_empty())
+        .collect();
+    if ranges.is_empty() {
+        return default;
+    }
+    let json = accept_quality(&ranges, ResponseFormat::Json).filter(|(q, _)| *q > 0.0);
+    let xml = accept_quality(&ranges, ResponseFormat::Xml).filter(|(q, _)| *q > 0.0);
+    match (json, xml) {
+        (_, None) => ResponseFormat::Json,
+        (None, Some(_)) => ResponseFormat::Xml,
+        (Some(json), Some(xml)) => match json.partial_cmp(&xml) {
+            Some(std::cmp::Ordering::Greater) => ResponseFormat::Json,
+            Some(std::cmp::Ordering::Less) => ResponseFormat::Xml,
+            _ => default,
+        },
+    }
+}
+
+// q-value of the most specific Accept range matching a format, with that specificity:
+// 2 for the type itself (any JSON or XML type), 1 for type/*, 0 for */*
+fn accept_quality(ranges: &[(String, f32)], format: ResponseFormat) -> Option<(f32, u8)> {
+    let (exact, families): (fn(&str) -> bool, &[&str]) = match format {
+        ResponseFormat::Json => (|media| media.ends_with("/json") || media.ends_with("+json"), &["application/*"]),
+        ResponseFormat::Xml => (|media| media == "application/xml" || media == "text/xml" || media.ends_with("+xml"), &["application/*", "text/*"]),
+    };
+    ranges
+        .iter()
+        .filter_map(|(media, q)| match media.as_str() {
+            media if exact(media) => Some((2, *q)),
+            media if families.contains(&media) => Some((1, *q)),
+            "*/*" => Some((0, *q)),
+            _ => None,
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(specificity, q)| (q, specificity))
+}
+
+// Middleware re-rendering JSON responses as XML when that is the negotiated format
+pub async fn content_negotiation(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let default = req
+        .app_data::<web::Data<Config>>()
+        .map_or(Config::default().default_content_type, |config| config.default_content_type);
+    let format = negotiate_format(&req, default);
+    let mut res = next.call(req).await?;
+    let json = res.headers().get(header::CONTENT_TYPE).is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if json {
+        res.headers_mut().append(header::VARY, HeaderValue::from_static("Accept"));
+    }
+    if format != ResponseFormat::Xml || !json {
+        return Ok(res.map_into_left_body());
+    }
+    let (req, res) = res.into_parts();
+    let (mut res, body) = res.into_parts();
+    let body = actix_web::body::to_bytes(body).await.map_err(|e| actix_web::error::ErrorInternalServerError(e.into().to_string()))?;
+    let Ok(value) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return Ok(ServiceResponse::new(req, res.set_body(body)).map_into_right_body());
+    };
+    res.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("application/xml"));
+    // A strong tag promises the JSON bytes; as a weak one it still matches If-None-Match
+    let etag = res.headers().get(header::ETAG).and_then(|etag| etag.to_str().ok()).filter(|etag| !etag.starts_with("W/")).map(|etag| format!("W/{}", etag));
+    if let Some(Ok(etag)) = etag.map(|etag| HeaderValue::from_str(&etag)) {
+        res.headers_mut().insert(header::ETAG, etag);
+    }
+    let xml = crate::xml::to_xml("response", &value);
+    Ok(ServiceResponse::new(req, res.set_body(xml.into())).map_into_right_body())
+}
+
+// Middleware answering HEAD with the GET response's status and headers but no body
+pub async fn head_as_get(mut req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let head = req.method() == Method::HEAD;
+    if head {
+        req.head_mut().method = Method::GET;
+    }
+    let res = next.call(req).await?;
+    if head {
+        return Ok(res.map_body(|_, _| ()).map_into_right_body());
+    }
+    Ok(res.map_into_left_body())
+}
+
+// Routes whose successful reads may be cached for cache_max_age_secs
+const CACHEABLE_PATTERNS: [&str; 2] = ["/books", "/books/{id}"];
+
+// Middleware setting Cache-Control: no-store on responses to writes and the configured max-age on
+// successful reads of the cacheable routes; responses vary per caller when API keys or tenants are
+// in use, which makes them private. A Cache-Control set by a handler is kept
+pub async fn cache_control(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let defaults = Config::default();
+    let config = req.app_data::<web::Data<Config>>().map_or(&defaults, |config| config.get_ref());
+    let read = req.method() == Method::GET || req.method() == Method::HEAD;
+    let cacheable = read && req.match_pattern().is_some_and(|pattern| CACHEABLE_PATTERNS.contains(&pattern.as_str()));
+    let private = !config.api_keys.is_empty() || config.tenant_isolation;
+    let max_age = format!("{}max-age={}", if private { "private, " } else { "" }, config.cache_max_age_secs);
+    let mut res = next.call(req).await?;
+    let value = if !read {
+        Some("no-store".to_string())
+    } else if cacheable && (res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED) {
+        Some(max_age)
+    } else {
+        None
+    };
+    if let Some(value) = value.filter(|_| !res.headers().contains_key(header::CACHE_CONTROL))
+        && let Ok(value) = HeaderValue::from_str(&value)
+    {
+        res.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    Ok(res)
+}
+
+// Middleware adding the configured security headers, leaving any a handler already set
+pub async fn security_headers(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let defaults = Config::default();
+    let config = req.app_data::<web::Data<Config>>().map_or(&defaults, |config| config.get_ref());
+    let mut headers = Vec::new();
+    if config.security_headers {
+        headers.push((header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()));
+        headers.push((header::X_FRAME_OPTIONS, "DENY".to_string()));
+    }
+    // Browsers ignore the header over plain HTTP, so it is only sent when this server terminates TLS
+    if config.tls_paths().is_some() && config.hsts_max_age_secs > 0 {
+        headers.push((header::STRICT_TRANSPORT_SECURITY, format!("max-age={}", config.hsts_max_age_secs)));
+    }
+    let mut res = next.call(req).await?;
+    for (name, value) in headers {
+        if !res.headers().contains_key(&name)
+            && let Ok(value) = HeaderValue::from_str(&value)
+        {
+            res.headers_mut().insert(name, value);
+        }
+    }
+    Ok(res)
+}
+
+// Build the CORS policy from the configured origins, headers, preflight max-age and credentials flag
+pub fn cors(config: &Config) -> Cors {
+    let mut cors = Cors::default().allow_any_method().max_age(config.cors_max_age);
+    cors = if config.cors_allowed_headers.iter().any(|name| name == "*") {
+        cors.allow_any_header()
+    } else {
+        // Names were checked when the configuration was loaded
+        cors.allowed_headers(config.cors_allowed_headers.iter().map(String::as_str))
+    };
+    for origin in &config.cors_allowed_origins {
+        cors = if origin == "*" { cors.allow_any_origin() } else { cors.allowed_origin(origin) };
+    }
+    if config.cors_allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+// Path normalization selected by TRAILING_SLASH; it has to run before routing, so it wraps the app
+pub fn normalize_path(config: &Config) -> Condition<NormalizePath> {
+    let mode = match config.trailing_slash {
+        TrailingSlashMode::Merge => TrailingSlash::MergeOnly,
+        TrailingSlashMode::Trim | TrailingSlashMode::Off => TrailingSlash::Trim,
+    };
+    Condition::new(config.trailing_slash != TrailingSlashMode::Off, NormalizePath::new(mode))
+}
+
+// actix's default access log format followed by the trace id set by `trace_context`
+const ACCESS_LOG_FORMAT: &str = "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T trace_id=%{X-Request-Id}o";
+
+// Build the access logger, skipping the configured paths
+pub fn access_logger(config: &Config) -> Logger {
+    config
+        .log_skip_paths
+        .iter()
+        .fold(Logger::new(ACCESS_LOG_FORMAT), |logger, path| logger.exclude(path.as_str()))
+}
+
+// Number of tracked clients above which expired windows are pruned
+const RATE_LIMIT_PRUNE_THRESHOLD: usize = 1024;
+
+// Quota state reported to a client for the current window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    // Seconds until the current window resets
+    pub reset_secs: u64,
+    pub allowed: bool,
+}
+
+// Fixed-window request limiter keyed by client address; disabled when no limit is set
+pub struct RateLimiter {
+    limit: Option<u32>,
+    window: Duration,
+    clients: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<u32>, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Count a request from the client, or None when limiting is disabled
+    pub fn check(&self, client: &str) -> Option<RateLimitStatus> {
+        let limit = self.limit?;
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() > RATE_LIMIT_PRUNE_THRESHOLD {
+            clients.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+        let (started, count) = clients.entry(client.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        let allowed = *count < limit;
+        if allowed {
+            *count += 1;
+        }
+        let reset = self.window.saturating_sub(now.duration_since(*started));
+        Some(RateLimitStatus {
+            limit,
+            remaining: limit - *count,
+            reset_secs: reset.as_secs() + u64::from(reset.subsec_nanos() > 0),
+            allowed,
+        })
+    }
+}
+
+// Middleware enforcing the rate limit and reporting quota headers on every response
+pub async fn rate_limit(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let client = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_else(|| "unknown".to_string());
+    let status = req.app_data::<web::Data<RateLimiter>>().and_then(|limiter| limiter.check(&client));
+    let Some(status) = status else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let mut res = if status.allowed {
+        next.call(req).await?.map_into_left_body()
+    } else {
+        req.error_response(ApiError::RateLimited(status.reset_secs)).map_into_right_body()
+    };
+    let headers = res.headers_mut();
+    for (name, value) in [
+        ("x-ratelimit-limit", u64::from(status.limit)),
+        ("x-ratelimit-remaining", u64::from(status.remaining)),
+        ("x-ratelimit-reset", status.reset_secs),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+    Ok(res)
+}
+
+// Middleware checking request bodies before the extractors see them: a Content-Type charset
+// other than utf-8 is a 415, and a JSON body that isn't UTF-8 once decompressed is a 400. The
+// decompressed body is handed on in place of the original, so it is only inflated once
+pub async fn body_encoding(mut req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let enabled = req.app_data::<web::Data<Config>>().is_none_or(|config| config.validate_body_encoding);
+    let media_type = match req.mime_type() {
+        Ok(Some(media_type)) if enabled => media_type,
+        _ => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+    if let Some(charset) = media_type.get_param(mime::CHARSET).filter(|charset| !matches!(charset.as_str().to_ascii_lowercase().as_str(), "utf-8" | "utf8")) {
+        let err = ApiError::UnsupportedCharset(charset.to_string());
+        return Ok(req.error_response(err).map_into_right_body());
+    }
+    if media_type.subtype() != mime::JSON && media_type.suffix() != Some(mime::JSON) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let decoded = Decoder::from_headers(req.take_payload(), req.headers());
+    let bytes = match body::to_bytes_limited(BodyStream::new(decoded), MAX_JSON_BODY_BYTES).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => return Ok(req.error_response(ApiError::InvalidJson(e.to_string())).map_into_right_body()),
+        Err(_) => return Ok(req.error_response(ApiError::BodyTooLarge).map_into_right_body()),
+    };
+    if let Err(e) = std::str::from_utf8(&bytes) {
+        return Ok(req.error_response(ApiError::InvalidEncoding(e.to_string())).map_into_right_body());
+    }
+    let headers = req.headers_mut();
+    headers.remove(header::CONTENT_ENCODING);
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(bytes.len()));
+    req.set_payload(Payload::from(bytes));
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{count_books, create_book, get_book, get_books, set_maintenance};
+    use crate::models::{Book, BookKind, MaintenanceState, NewBook};
+    use crate::store::{Books, Store};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use tokio::sync::RwLock;
+
+    // Logger capturing INFO and more severe records so tests can assert on them
+    struct CaptureLogger;
+
+    static CAPTURED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static CAPTURE_LOGGER: CaptureLogger = CaptureLogger;
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                CAPTURED.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[actix_web::test]
+    async fn test_maintenance_mode_blocks_writes_but_allows_reads() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new()
+        .wrap(from_fn(maintenance_guard))
+        .app_data(web::Data::new(Config::default()))
+        .app_data(web::Data::new(Maintenance::new(true)))
+        .app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::get().to(get_books)).route(web::post().to(create_book)))).await;
+
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&NewBook {
+                title: "Book Title".to_string(),
+                author: "Book Author".to_string(),
+                genre: None,
+                language: None,
+                kind: None,
+                issue_number: None,
+                isbn: None,
+                published_year: None,
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 503);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "60");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "maintenance");
+        assert!(store.read().await.books.is_empty());
+
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[actix_web::test]
+    async fn test_admin_maintenance_toggle() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            admin_api_key: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(maintenance_guard))
+        .app_data(web::Data::new(config))
+        .app_data(web::Data::new(Maintenance::default()))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::post().to(create_book)))
+        .service(web::scope("/admin").wrap(from_fn(require_api_key)).route("/maintenance", web::post().to(set_maintenance)))).await;
+        let create = || {
+            test::TestRequest::post()
+                .uri("/books")
+                .set_json(&NewBook {
+                    title: "Book Title".to_string(),
+                    author: "Book Author".to_string(),
+                    genre: None,
+                    language: None,
+                    kind: None,
+                    issue_number: None,
+                    isbn: None,
+                    published_year: None,
+                })
+                .to_request()
+        };
+        let toggle = |enabled: bool| {
+            test::TestRequest::post()
+                .uri("/admin/maintenance")
+                .insert_header((API_KEY_HEADER, "secret"))
+                .set_json(&MaintenanceState { enabled })
+                .to_request()
+        };
+
+        let req = test::TestRequest::post()
+            .uri("/admin/maintenance")
+            .set_json(&MaintenanceState { enabled: true })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 401);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unauthorized");
+
+        let state: MaintenanceState = test::call_and_read_body_json(&app, toggle(true)).await;
+        assert!(state.enabled);
+        let res = test::call_service(&app, create()).await;
+        assert_eq!(res.status(), 503);
+
+        let state: MaintenanceState = test::call_and_read_body_json(&app, toggle(false)).await;
+        assert!(!state.enabled);
+        let res = test::call_service(&app, create()).await;
+        assert_eq!(res.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limit_headers_count_down() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new()
+        .wrap(from_fn(rate_limit))
+        .app_data(web::Data::new(Config::default()))
+        .app_data(web::Data::new(RateLimiter::new(Some(2), Duration::from_secs(60))))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        for expected_remaining in ["1", "0"] {
+            let req = test::TestRequest::get().uri("/books").to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.headers().get("x-ratelimit-limit").unwrap(), "2");
+            assert_eq!(res.headers().get("x-ratelimit-remaining").unwrap(), expected_remaining);
+            assert_eq!(res.headers().get("x-ratelimit-reset").unwrap(), "60");
+        }
+
+        let req = test::TestRequest::get().uri("/books").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers().get("x-ratelimit-remaining").unwrap(), "0");
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "60");
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "rate_limited");
+    }
+
+    #[actix_web::test]
+    async fn test_cors_preflight_max_age_and_credentials() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://app.example".to_string()],
+            cors_max_age: 600,
+            cors_allow_credentials: true,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(cors(&config))
+        .app_data(web::Data::new(Config::default()))
+        .app_data(web::Data::new(Arc::new(RwLock::new(Store::default()))))
+        .service(web::resource("/books").route(web::get().to(get_books)))).await;
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/books")
+            .insert_header((header::ORIGIN, "https://app.example"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(res.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://app.example");
+    }
+
+    #[actix_web::test]
+    async fn test_cors_preflight_lists_configured_headers() {
+        let config = Config {
+            cors_allowed_origins: vec!["https://app.example".to_string()],
+            cors_allowed_headers: vec!["Content-Type".to_string(), "X-Trace-Id".to_string()],
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(cors(&config))
+        .route("/books", web::get().to(HttpResponse::Ok))).await;
+        let preflight = |headers: &str| {
+            test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/books")
+                .insert_header((header::ORIGIN, "https://app.example"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+                .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, headers.to_string()))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, preflight("x-trace-id")).await;
+        assert_eq!(res.status(), 200);
+        let allowed = res.headers().get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap().to_lowercase();
+        assert!(allowed.contains("x-trace-id"), "{}", allowed);
+        assert!(allowed.contains("content-type"), "{}", allowed);
+
+        let res = test::try_call_service(&app, preflight("x-unlisted")).await;
+        assert!(res.map_or(true, |res| res.status().is_client_error()));
+    }
+
+    #[actix_web::test]
+    async fn test_slow_request_is_logged() {
+        let _ = log::set_logger(&CAPTURE_LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        let config = Config {
+            slow_request_ms: 10,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(slow_request_log))
+        .app_data(web::Data::new(config))
+        .route("/slow", web::get().to(|| async {
+            actix_web::rt::time::sleep(Duration::from_millis(30)).await;
+            HttpResponse::Ok().finish()
+        }))
+        .route("/fast", web::get().to(HttpResponse::Ok))).await;
+
+        for uri in ["/slow", "/fast"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+        let captured = CAPTURED.lock().unwrap();
+        assert!(captured.iter().any(|line| line.starts_with("slow request: GET /slow took ")));
+        assert!(!captured.iter().any(|line| line.contains("/fast")));
+    }
+
+    #[actix_web::test]
+    async fn test_access_log_skips_configured_paths() {
+        let _ = log::set_logger(&CAPTURE_LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        let config = Config {
+            log_skip_paths: vec!["/probe".to_string()],
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(access_logger(&config))
+        .route("/probe", web::get().to(HttpResponse::Ok))
+        .route("/logged", web::get().to(HttpResponse::Ok))).await;
+
+        for uri in ["/probe", "/logged"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+        let captured = CAPTURED.lock().unwrap();
+        assert!(captured.iter().any(|line| line.contains("GET /logged")));
+        assert!(!captured.iter().any(|line| line.contains("/probe")));
+    }
+
+    #[actix_web::test]
+    async fn test_compression_skips_small_responses() {
+        let store: Books = Arc::new(RwLock::new(Store::from(
+            (1..=50).map(|id| Book {
+                id,
+                title: format!("A reasonably long book title number {}", id),
+                author: "Some Author".to_string(),
+                genre: None,
+                language: None,
+                kind: BookKind::Book,
+                issue_number: None,
+                position: None,
+                tags: Vec::new(),
+                isbn: None,
+                published_year: None,
+                updated_at: 0,
+                version: 1,
+                owner: None,
+                tenant_id: None,
+            }).collect::<Vec<_>>(),
+        )));
+        let app = test::init_service(App::new()
+        .wrap(from_fn(compress))
+        .app_data(web::Data::new(Config::default()))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)))
+        .service(web::resource("/books/count").route(web::get().to(count_books)))).await;
+
+        let req = test::TestRequest::get().uri("/books/count").insert_header((header::ACCEPT_ENCODING, "gzip")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let req = test::TestRequest::get().uri("/books").insert_header((header::ACCEPT_ENCODING, "gzip")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap(), "gzip");
+    }
+
+    #[actix_web::test]
+    async fn test_auth_context_carries_principal_and_requires_one_with_api_keys() {
+        async fn whoami(ctx: AuthContext) -> HttpResponse {
+            HttpResponse::Ok().body(ctx.principal.map(|principal| principal.name).unwrap_or_default())
+        }
+        let config = Config {
+            api_keys: [("alice-key".to_string(), "alice".to_string())].into_iter().collect(),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(authenticate))
+        .app_data(web::Data::new(config.clone()))
+        .route("/whoami", web::get().to(whoami))).await;
+        let req = test::TestRequest::get().uri("/whoami").insert_header((API_KEY_HEADER, "alice-key")).to_request();
+        let body = test::call_and_read_body(&app, req).await;
+        assert_eq!(body, "alice");
+
+        // Without the middleware in front nothing attaches a principal, so extraction itself refuses
+        let app = test::init_service(App::new().app_data(web::Data::new(config)).route("/whoami", web::get().to(whoami))).await;
+        let res = test::call_service(&app, test::TestRequest::get().uri("/whoami").to_request()).await;
+        assert_eq!(res.status(), 401);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unauthorized");
+    }
+
+    #[actix_web::test]
+    async fn test_problem_details_for_missing_book() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new()
+        .wrap(from_fn(problem_details))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books/42").insert_header((header::ACCEPT, "application/problem+json")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body, serde_json::json!({
+            "type": "about:blank",
+            "title": "Not Found",
+            "status": 404,
+            "detail": "book not found",
+            "instance": "/books/42",
+            "code": "not_found",
+        }));
+
+        let req = test::TestRequest::get().uri("/books/42").insert_header((header::ACCEPT, "application/json")).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["error"]["code"], "not_found");
+    }
+
+    #[actix_web::test]
+    async fn test_problem_details_for_requests_rejected_by_middleware() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            api_keys: [("alice-key".to_string(), "alice".to_string())].into_iter().collect(),
+            ..Config::default()
+        };
+        // The same layering as main.rs
+        let app = test::init_service(App::new()
+        .wrap(from_fn(require_tenant))
+        .wrap(from_fn(authenticate))
+        .wrap(from_fn(maintenance_guard))
+        .wrap(from_fn(rate_limit))
+        .wrap(from_fn(problem_details))
+        .wrap(from_fn(content_negotiation))
+        .app_data(web::Data::new(config))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        let req = test::TestRequest::get().uri("/books/42").insert_header((header::ACCEPT, "application/problem+json")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 401);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], 401);
+        assert_eq!(body["code"], "unauthorized");
+    }
+
+    #[actix_web::test]
+    async fn test_traceparent_trace_id_is_logged_and_echoed() {
+        let _ = log::set_logger(&CAPTURE_LOGGER);
+        log::set_max_level(log::LevelFilter::Info);
+        let app = test::init_service(App::new()
+        .wrap(from_fn(trace_context))
+        .wrap(access_logger(&Config::default()))
+        .route("/traced", web::get().to(HttpResponse::Ok))).await;
+
+        let req = test::TestRequest::get()
+            .uri("/traced")
+            .insert_header(("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(REQUEST_ID_HEADER).unwrap(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        // The access log line is written once the response body is done with
+        drop(res);
+        assert!(CAPTURED.lock().unwrap().iter().any(|line| line.contains("GET /traced") && line.contains("trace_id=4bf92f3577b34da6a3ce929d0e0e4736")));
+
+        // A missing or malformed header starts a new trace
+        let req = test::TestRequest::get().uri("/traced").insert_header(("traceparent", "00-00000000000000000000000000000000-00f067aa0ba902b7-01")).to_request();
+        let res = test::call_service(&app, req).await;
+        let trace_id = res.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap();
+        assert_eq!(trace_id.len(), 32);
+        assert_ne!(trace_id, "00000000000000000000000000000000");
+    }
+
+    #[actix_web::test]
+    async fn test_traceparent_parsing() {
+        let trace = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(trace.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(trace.parent_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert!(TraceContext::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra").is_some());
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{:?}", invalid);
+        }
+        assert_ne!(TraceContext::new_trace().trace_id, TraceContext::new_trace().trace_id);
+    }
+
+    #[actix_web::test]
+    async fn test_security_headers_follow_config() {
+        let tls = Config {
+            tls_cert_path: Some("cert.pem".into()),
+            tls_key_path: Some("key.pem".into()),
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(security_headers))
+        .app_data(web::Data::new(tls.clone()))
+        .route("/", web::get().to(HttpResponse::Ok))).await;
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        assert_eq!(res.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(res.headers().get(header::X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert_eq!(res.headers().get(header::STRICT_TRANSPORT_SECURITY).unwrap(), "max-age=31536000");
+
+        // Without TLS there is no HSTS, and the env toggles turn the rest off
+        for (config, sniff, hsts) in [
+            (Config::default(), true, false),
+            (Config { security_headers: false, hsts_max_age_secs: 0, ..tls }, false, false),
+        ] {
+            let app = test::init_service(App::new()
+            .wrap(from_fn(security_headers))
+            .app_data(web::Data::new(config))
+            .route("/", web::get().to(HttpResponse::Ok))).await;
+            let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+            assert_eq!(res.headers().contains_key(header::X_CONTENT_TYPE_OPTIONS), sniff);
+            assert_eq!(res.headers().contains_key(header::STRICT_TRANSPORT_SECURITY), hsts);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_cache_control_on_reads_and_writes() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let config = Config {
+            cache_max_age_secs: 120,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(cache_control))
+        .app_data(web::Data::new(config))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books").route(web::get().to(get_books)).route(web::post().to(create_book)))
+        .service(web::resource("/books/count").route(web::get().to(count_books)))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+        let directive = |headers: &header::HeaderMap| headers.get(header::CACHE_CONTROL).map(|value| value.to_str().unwrap().to_string());
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/books").to_request()).await;
+        assert_eq!(directive(res.headers()).as_deref(), Some("max-age=120"));
+        let req = test::TestRequest::post().uri("/books").set_json(serde_json::json!({"title": "Dune", "author": "Frank Herbert"})).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(directive(res.headers()).as_deref(), Some("no-store"));
+        let res = test::call_service(&app, test::TestRequest::get().uri("/books/1").to_request()).await;
+        assert_eq!(directive(res.headers()).as_deref(), Some("max-age=120"));
+
+        // Other routes and failed reads are left to the defaults
+        let res = test::call_service(&app, test::TestRequest::get().uri("/books/count").to_request()).await;
+        assert_eq!(directive(res.headers()), None);
+        let res = test::call_service(&app, test::TestRequest::get().uri("/books/9").to_request()).await;
+        assert_eq!(res.status(), 404);
+        assert_eq!(directive(res.headers()), None);
+    }
+
+    #[actix_web::test]
+    async fn test_default_content_type_applies_without_accept() {
+        let store: Books = Arc::new(RwLock::new(Store::from(vec![Book {
+            id: 1,
+            title: "Dune".to_string(),
+            author: "Frank Herbert".to_string(),
+            genre: None,
+            language: None,
+            kind: BookKind::Book,
+            issue_number: None,
+            position: None,
+            tags: Vec::new(),
+            isbn: None,
+            published_year: None,
+            updated_at: 0,
+            version: 1,
+            owner: None,
+            tenant_id: None,
+        }])));
+        let config = Config {
+            default_content_type: ResponseFormat::Xml,
+            ..Config::default()
+        };
+        let app = test::init_service(App::new()
+        .wrap(from_fn(content_negotiation))
+        .app_data(web::Data::new(config))
+        .app_data(web::Data::new(store))
+        .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+
+        for accept in [None, Some("*/*")] {
+            let mut req = test::TestRequest::get().uri("/books/1");
+            if let Some(accept) = accept {
+                req = req.insert_header((header::ACCEPT, accept));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "application/xml");
+            assert_eq!(res.headers().get(header::ETAG).unwrap(), "W/\"1\"");
+            let body = test::read_body(res).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains("<title>Dune</title>"), "{:?}", accept);
+        }
+
+        let req = test::TestRequest::get().uri("/books/1").insert_header((header::ACCEPT, "application/json")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+
+        // JSON stays the default when nothing is configured
+        assert_eq!(Config::from_lookup(|_| None).unwrap().default_content_type, ResponseFormat::Json);
+        let config = Config::from_lookup(|name| (name == "DEFAULT_CONTENT_TYPE").then(|| "xml".to_string())).unwrap();
+        assert_eq!(config.default_content_type, ResponseFormat::Xml);
+    }
+
+    #[actix_web::test]
+    async fn test_accept_q_values_pick_the_format() {
+        let negotiate = |accept: &str, default: ResponseFormat| {
+            negotiate_format(&test::TestRequest::get().insert_header((header::ACCEPT, accept)).to_srv_request(), default)
+        };
+        for (accept, expected) in [
+            ("application/xml;q=0.9, application/json;q=1.0", ResponseFormat::Json),
+            ("application/json;q=0.5, application/xml", ResponseFormat::Xml),
+            ("application/json; q=0.8, text/xml; q=0.9", ResponseFormat::Xml),
+            ("application/xml;q=0, */*", ResponseFormat::Json),
+            ("application/*;q=0.2, application/json;q=0.1", ResponseFormat::Xml),
+            ("*/*;q=0.5, application/json;q=0.5", ResponseFormat::Json),
+            ("text/html", ResponseFormat::Json),
+        ] {
+            assert_eq!(negotiate(accept, ResponseFormat::Json), expected, "{}", accept);
+            assert_eq!(negotiate(accept, ResponseFormat::Xml), expected, "{}", accept);
+        }
+        // On a tie, named or through wildcards, the configured default decides
+        assert_eq!(negotiate("*/*;q=0.3", ResponseFormat::Xml), ResponseFormat::Xml);
+        assert_eq!(negotiate("application/*", ResponseFormat::Json), ResponseFormat::Json);
+        assert_eq!(negotiate("application/json, application/xml", ResponseFormat::Json), ResponseFormat::Json);
+        assert_eq!(negotiate("application/xml, application/json", ResponseFormat::Json), ResponseFormat::Json);
+        assert_eq!(negotiate("application/json, application/xml", ResponseFormat::Xml), ResponseFormat::Xml);
+    }
+
+    #[actix_web::test]
+    async fn test_trailing_slash_modes() {
+        for (mode, expected) in [
+            (TrailingSlashMode::Trim, [("/books", 200), ("/books/", 200), ("//books", 200), ("/books/1/", 200)]),
+            (TrailingSlashMode::Merge, [("/books", 200), ("/books/", 404), ("//books", 200), ("/books/1/", 404)]),
+            (TrailingSlashMode::Off, [("/books", 200), ("/books/", 404), ("//books", 404), ("/books/1/", 404)]),
+        ] {
+            let config = Config {
+                trailing_slash: mode,
+                ..Config::default()
+            };
+            let store: Books = Arc::new(RwLock::new(Store::from(vec![Book {
+                id: 1,
+                title: "Dune".to_string(),
+                author: "Frank Herbert".to_string(),
+                genre: None,
+                language: None,
+                kind: BookKind::Book,
+                issue_number: None,
+                position: None,
+                tags: Vec::new(),
+                isbn: None,
+                published_year: None,
+                updated_at: 0,
+                version: 1,
+                owner: None,
+                tenant_id: None,
+            }])));
+            let app = test::init_service(App::new().wrap(normalize_path(&config)).app_data(web::Data::new(config)).app_data(web::Data::new(store))
+            .service(web::resource("/books").route(web::get().to(get_books)))
+            .service(web::resource("/books/{id}").route(web::get().to(get_book)))).await;
+            for (path, status) in expected {
+                let res = test::call_service(&app, test::TestRequest::get().uri(path).to_request()).await;
+                assert_eq!(res.status(), status, "{:?} {}", mode, path);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_body_encoding_rejects_non_utf8_bodies_and_charsets() {
+        let store: Books = Arc::new(RwLock::new(Store::default()));
+        let app = test::init_service(App::new().wrap(from_fn(body_encoding)).app_data(crate::errors::json_config()).app_data(web::Data::new(store.clone()))
+        .service(web::resource("/books").route(web::post().to(create_book)))).await;
+        let post = |content_type: &str, body: &[u8]| {
+            test::TestRequest::post()
+                .uri("/books")
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .set_payload(body.to_vec())
+                .to_request()
+        };
+
+        // "Émile" in Latin-1
+        let res = test::call_service(&app, post("application/json", b"{\"title\": \"\xc9mile\", \"author\": \"Rousseau\"}")).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "invalid_encoding");
+
+        let res = test::call_service(&app, post("application/json; charset=iso-8859-1", br#"{"title": "Emile", "author": "Rousseau"}"#)).await;
+        assert_eq!(res.status(), 415);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "unsupported_charset");
+        assert!(store.read().await.books.is_empty());
+
+        let res = test::call_service(&app, post("application/json; charset=UTF-8", "{\"title\": \"Émile\", \"author\": \"Rousseau\"}".as_bytes())).await;
+        assert_eq!(res.status(), 201);
+        assert_eq!(store.read().await.books[0].title, "Émile");
+
+        // Compressed bodies are checked once inflated, and the extractor doesn't inflate them again
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, br#"{"title": "Dune", "author": "Frank Herbert"}"#).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .insert_header((header::CONTENT_ENCODING, "gzip"))
+            .set_payload(encoder.finish().unwrap())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+    }
+}
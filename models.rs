@@ -0,0 +1,674 @@
+use crate::patch::PatchOperation;
+use actix_web::HttpRequest;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Define a struct to represent a book
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Book {
+    pub id: i32,
+    #[schemars(length(min = 1))]
+    pub title: String,
+    #[schemars(length(min = 1))]
+    pub author: String,
+    // Optional classification; records saved before it existed load as None
+    #[serde(default)]
+    pub genre: Option<String>,
+    // BCP 47 language tag such as "en" or "fr-CA"
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub kind: BookKind,
+    // Issue of a magazine; books have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issue_number: Option<u32>,
+    // Place in the manually ordered reading list, set through POST /books/reorder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    // Free-form labels, unique ignoring case
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // ISBN-10 or ISBN-13, as entered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isbn: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_year: Option<i32>,
+    // Last modification time in milliseconds since the Unix epoch
+    #[serde(default)]
+    pub updated_at: u64,
+    // Incremented on every change, used for If-Match preconditions
+    #[serde(default = "initial_version")]
+    pub version: u32,
+    // Principal that created the book, when it was created by an authenticated caller
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    // Tenant the book belongs to when tenant isolation is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+// Kind of publication, which decides the fields a payload must carry
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BookKind {
+    #[default]
+    Book,
+    Magazine,
+}
+
+impl BookKind {
+    // Resolve a payload's kind name, None when it is not a known kind
+    pub fn parse(kind: &str) -> Option<BookKind> {
+        match kind {
+            "book" => Some(BookKind::Book),
+            "magazine" => Some(BookKind::Magazine),
+            _ => None,
+        }
+    }
+
+    // Name used in payloads, the inverse of parse
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BookKind::Book => "book",
+            BookKind::Magazine => "magazine",
+        }
+    }
+}
+
+// Check the shape of a BCP 47 tag: a 2-3 or 5-8 letter primary language, then
+// hyphen-separated subtags of 1-8 letters or digits (e.g. "en", "fr-CA", "zh-Hant-TW")
+pub fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    matches!(primary.len(), 2..=3 | 5..=8)
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+// Check an ISBN-10 or ISBN-13 checksum, ignoring hyphens and spaces; an ISBN-10 may end in X
+pub fn is_isbn(isbn: &str) -> bool {
+    let chars: Vec<char> = isbn.chars().filter(|c| *c != '-' && *c != ' ').collect();
+    let digit = |c: char| c.to_digit(10);
+    match chars.len() {
+        10 => {
+            let mut sum = 0;
+            for (i, c) in chars.iter().enumerate() {
+                let value = match (i, *c) {
+                    (9, 'X' | 'x') => 10,
+                    (_, c) => match digit(c) {
+                        Some(value) => value,
+                        None => return false,
+                    },
+                };
+                sum += (10 - i as u32) * value;
+            }
+            sum % 11 == 0
+        }
+        13 => {
+            let digits: Option<Vec<u32>> = chars.iter().map(|c| digit(*c)).collect();
+            digits.is_some_and(|digits| digits.iter().enumerate().map(|(i, d)| if i % 2 == 0 { *d } else { d * 3 }).sum::<u32>() % 10 == 0)
+        }
+        _ => false,
+    }
+}
+
+// A valid ISBN without hyphens or spaces and with an upper-case X, None for an invalid one
+pub fn normalize_isbn(isbn: &str) -> Option<String> {
+    is_isbn(isbn).then(|| isbn.chars().filter(|c| *c != '-' && *c != ' ').collect::<String>().to_uppercase())
+}
+
+// Whether a book's language falls under a requested tag, so "en" also matches "en-GB" (RFC 4647 basic filtering)
+pub fn language_matches(language: Option<&str>, requested: &str) -> bool {
+    language.is_some_and(|language| {
+        let (language, requested) = (language.to_ascii_lowercase(), requested.to_ascii_lowercase());
+        language == requested || language.starts_with(&format!("{}-", requested))
+    })
+}
+
+impl Book {
+    // Replace the content fields with those of another book, keeping id, owner, tenant and bookkeeping
+    pub fn set_content(&mut self, book: Book) {
+        self.title = book.title;
+        self.author = book.author;
+        self.genre = book.genre;
+        self.language = book.language;
+        self.kind = book.kind;
+        self.issue_number = book.issue_number;
+        self.position = book.position;
+        self.tags = book.tags;
+        self.isbn = book.isbn;
+        self.published_year = book.published_year;
+    }
+}
+
+fn initial_version() -> u32 {
+    1
+}
+
+// Define a struct to represent a new book; schema constraints mirror SchemaVersion::validate
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct NewBook {
+    #[schemars(length(min = 1))]
+    pub title: String,
+    #[schemars(length(min = 1))]
+    pub author: String,
+    // Must be on GENRE_ALLOWLIST when one is configured; updates without a genre keep the current one
+    #[serde(default)]
+    pub genre: Option<String>,
+    // BCP 47 language tag such as "en" or "fr-CA"
+    #[serde(default)]
+    pub language: Option<String>,
+    // "book" (the default) or "magazine"; kept as text so unknown kinds can be told apart from malformed bodies
+    #[serde(default)]
+    #[schemars(extend("enum" = ["book", "magazine", null]))]
+    pub kind: Option<String>,
+    // Required when kind is "magazine"
+    #[serde(default)]
+    pub issue_number: Option<u32>,
+    // ISBN-10 or ISBN-13; hyphens and spaces are allowed
+    #[serde(default)]
+    pub isbn: Option<String>,
+    #[serde(default)]
+    pub published_year: Option<i32>,
+}
+
+impl From<&Book> for NewBook {
+    fn from(book: &Book) -> Self {
+        NewBook {
+            title: book.title.clone(),
+            author: book.author.clone(),
+            genre: book.genre.clone(),
+            language: book.language.clone(),
+            kind: Some(book.kind.as_str().to_string()),
+            issue_number: book.issue_number,
+            isbn: book.isbn.clone(),
+            published_year: book.published_year,
+        }
+    }
+}
+
+// Define a struct to represent a partial change to a book
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BookChanges {
+    pub title: Option<String>,
+    pub author: Option<String>,
+}
+
+impl BookChanges {
+    // Apply the fields that are set to the given book
+    pub fn apply(&self, book: &mut Book) {
+        if let Some(title) = &self.title {
+            book.title = title.clone();
+        }
+        if let Some(author) = &self.author {
+            book.author = author.clone();
+        }
+    }
+}
+
+// Define a struct to represent the same change applied to many books
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BatchUpdate {
+    pub ids: Vec<i32>,
+    pub changes: BookChanges,
+}
+
+// Define a struct to represent the outcome of a batch update
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BatchUpdateResult {
+    pub updated: Vec<Book>,
+    pub missing: Vec<i32>,
+}
+
+// Define a struct to represent a JSON Patch for one book of a batch
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BookPatch {
+    pub id: i32,
+    pub patch: Vec<PatchOperation>,
+}
+
+// Define a struct to represent the outcome of one book of a batched JSON Patch
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct PatchOutcome {
+    pub id: i32,
+    // Status the item would have had as a request of its own
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book: Option<Book>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ItemError>,
+}
+
+// Define a struct to represent why one item of a batch failed
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct ItemError {
+    pub code: String,
+    pub message: String,
+}
+
+// Define a struct to represent aggregate statistics about the collection
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BookStats {
+    pub total_books: usize,
+    pub distinct_authors: usize,
+    // Most recent updated_at across all books, if any
+    pub last_updated_at: Option<u64>,
+}
+
+// Define a struct to represent the outcome of a deduplication run
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct DedupeReport {
+    pub dry_run: bool,
+    // Removed (or, on a dry run, removable) ids keyed by the id that was kept
+    pub removed: BTreeMap<i32, Vec<i32>>,
+}
+
+// Define a struct to represent one author spelling chosen for its case-insensitive variants
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct AuthorRewrite {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    // Spelling every variant is rewritten to
+    pub author: String,
+    pub variants: Vec<String>,
+    // Number of books rewritten (or, on a dry run, to rewrite)
+    pub books: usize,
+}
+
+// Define a struct to represent the outcome of an author normalization run
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct NormalizeAuthorsReport {
+    pub dry_run: bool,
+    pub rewrites: Vec<AuthorRewrite>,
+}
+
+// Define a struct to represent how many books an author has
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct AuthorCount {
+    pub author: String,
+    pub count: usize,
+}
+
+// Define a struct to represent how many books were published in a decade
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct DecadeCount {
+    // First year of the decade, e.g. 1990 for 1990-1999
+    pub decade: i32,
+    pub count: usize,
+}
+
+// Define a struct to represent the check of one ISBN submitted for validation
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct IsbnCheck {
+    // As submitted
+    pub isbn: String,
+    pub valid: bool,
+    pub normalized: Option<String>,
+}
+
+// Define a struct to represent a broken store invariant found by the self-check
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Violation {
+    pub id: i32,
+    pub problem: String,
+}
+
+// Define a struct to report on a store self-check
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct SelfCheckReport {
+    pub books: usize,
+    pub violations: Vec<Violation>,
+}
+
+// Define a struct to represent books sharing a normalized title and author
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct DuplicateGroup {
+    // Title and author of the lowest id in the group
+    pub title: String,
+    pub author: String,
+    pub ids: Vec<i32>,
+}
+
+// Define a struct to report on a search index rebuild
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct ReindexReport {
+    pub books: usize,
+    pub tokens: usize,
+    pub elapsed_micros: u64,
+}
+
+// Define a struct to report on a cache warmup
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct WarmupReport {
+    // List queries primed, the unfiltered list included
+    pub queries: usize,
+    pub tokens: usize,
+    pub elapsed_micros: u64,
+}
+
+// Define a struct to report how the books of an import were applied
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct ImportReport {
+    pub created: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+}
+
+// State of a background job
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+// Define a struct to represent a background import and, once finished, its outcome
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Job {
+    pub id: u64,
+    pub status: JobStatus,
+    // Number of books submitted
+    pub total: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<ImportReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, JobStatus::Done | JobStatus::Failed)
+    }
+}
+
+// Define a struct to report whether a title and author pair is already stored
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct ExistsResult {
+    pub exists: bool,
+    // Lowest matching id when the pair exists
+    pub id: Option<i32>,
+}
+
+// Define a struct to describe the page of a list response
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Pagination {
+    pub total: usize,
+    // Requested page size; None when the whole list was returned
+    pub limit: Option<usize>,
+    pub offset: usize,
+    pub total_pages: usize,
+    // 1-based page the offset falls on, 0 when there are no pages
+    pub current_page: usize,
+}
+
+impl Pagination {
+    pub fn new(total: usize, limit: Option<usize>, offset: usize) -> Self {
+        let (total_pages, current_page) = match (total, limit) {
+            (0, _) => (0, 0),
+            (_, None) => (1, 1),
+            (total, Some(limit)) => (total.div_ceil(limit), offset / limit + 1),
+        };
+        Pagination {
+            total,
+            limit,
+            offset,
+            total_pages,
+            current_page,
+        }
+    }
+}
+
+// Define a struct to wrap list data with its pagination metadata
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Envelope<T> {
+    pub data: T,
+    pub pagination: Pagination,
+    // Set when a search matched more than MAX_SEARCH_RESULTS books and the rest were dropped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+// Define a struct to select books for bulk operations; every set field must match
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct BookFilter {
+    // Same matching as the list endpoint's q parameter
+    pub q: Option<String>,
+    // Case- and whitespace-insensitive equality
+    pub author: Option<String>,
+    pub title: Option<String>,
+}
+
+impl BookFilter {
+    pub fn is_empty(&self) -> bool {
+        self.q.is_none() && self.author.is_none() && self.title.is_none()
+    }
+}
+
+// Define a struct to represent a genre assignment over filtered books
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct GenreAssignment {
+    pub filter: BookFilter,
+    pub genre: String,
+}
+
+// Define a struct to represent tags to add to and remove from a book
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct TagChanges {
+    #[serde(default)]
+    pub add: Vec<String>,
+    #[serde(default)]
+    pub remove: Vec<String>,
+}
+
+impl TagChanges {
+    // Apply removals, then additions, comparing tags without regard to case
+    pub fn apply(&self, tags: &mut Vec<String>) {
+        let removed: Vec<String> = self.remove.iter().map(|tag| tag.trim().to_lowercase()).collect();
+        tags.retain(|tag| !removed.contains(&tag.to_lowercase()));
+        for tag in self.add.iter().map(|tag| tag.trim()) {
+            if !tags.iter().any(|existing| existing.to_lowercase() == tag.to_lowercase()) {
+                tags.push(tag.to_string());
+            }
+        }
+    }
+}
+
+// Define a struct to represent a new reading-list order, first id first
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Reorder {
+    pub order: Vec<i32>,
+}
+
+// Kind of change recorded in a book's history
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+// Define a struct to represent one entry of a book's change history
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct HistoryEntry {
+    pub change: ChangeKind,
+    pub timestamp: u64,
+    // State of the book right after the change
+    pub book: Book,
+}
+
+// Define a struct to represent a deleted book in the changes feed
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct Tombstone {
+    pub id: i32,
+    pub deleted_at: u64,
+}
+
+// Define a struct to represent the books changed since a point in time
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct ChangesFeed {
+    // Created or updated books in their current state, oldest change first
+    pub updated: Vec<Book>,
+    pub deleted: Vec<Tombstone>,
+    // Value to pass as `since` on the next poll
+    pub until: u64,
+}
+
+// Define a struct to represent the maintenance mode state
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct MaintenanceState {
+    pub enabled: bool,
+}
+
+// Define a struct to represent the runtime log level, e.g. "debug"
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "camel-case", serde(rename_all = "camelCase"))]
+pub struct LogLevelState {
+    pub level: String,
+}
+
+// Current time in milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Header used by clients to declare which NewBook schema version they send
+pub const SCHEMA_VERSION_HEADER: &str = "X-Schema-Version";
+
+// Supported versions of the NewBook payload schema
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaVersion {
+    V1,
+}
+
+impl SchemaVersion {
+    pub const LATEST: SchemaVersion = SchemaVersion::V1;
+
+    // Resolve the schema version declared by the request, defaulting to the latest
+    pub fn from_request(req: &HttpRequest) -> Result<SchemaVersion, String> {
+        let header = match req.headers().get(SCHEMA_VERSION_HEADER) {
+            Some(header) => header,
+            None => return Ok(SchemaVersion::LATEST),
+        };
+        let value = header
+            .to_str()
+            .map_err(|_| format!("{} header must be valid ASCII", SCHEMA_VERSION_HEADER))?;
+        match value.trim() {
+            "1" => Ok(SchemaVersion::V1),
+            other => Err(format!("Unsupported schema version: {}", other)),
+        }
+    }
+
+    // Check a payload against the rules of this schema version
+    pub fn validate(self, new_book: &NewBook) -> Result<(), String> {
+        self.validate_fields(Some(&new_book.title), Some(&new_book.author))?;
+        let kind = new_book.kind.as_deref().and_then(BookKind::parse).unwrap_or_default();
+        if kind == BookKind::Magazine && new_book.issue_number.is_none() {
+            return Err("issue_number is required for magazines".to_string());
+        }
+        if let Some(isbn) = new_book.isbn.as_deref().filter(|isbn| !is_isbn(isbn)) {
+            return Err(format!("invalid ISBN: {:?}", isbn));
+        }
+        Ok(())
+    }
+
+    // Check a partial payload, validating only the fields it sets
+    pub fn validate_changes(self, changes: &BookChanges) -> Result<(), String> {
+        if changes.title.is_none() && changes.author.is_none() {
+            return Err("changes must set at least one field".to_string());
+        }
+        self.validate_fields(changes.title.as_deref(), changes.author.as_deref())
+    }
+
+    fn validate_fields(self, title: Option<&str>, author: Option<&str>) -> Result<(), String> {
+        match self {
+            SchemaVersion::V1 => {
+                if title.is_some_and(|title| title.trim().is_empty()) {
+                    return Err("title must not be empty".to_string());
+                }
+                if author.is_some_and(|author| author.trim().is_empty()) {
+                    return Err("author must not be empty".to_string());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wire name of updated_at in the convention selected at build time
+    #[cfg(feature = "camel-case")]
+    const UPDATED_AT: &str = "updatedAt";
+    #[cfg(not(feature = "camel-case"))]
+    const UPDATED_AT: &str = "updated_at";
+
+    #[actix_web::test]
+    async fn test_book_round_trips_in_selected_naming_convention() {
+        let json = serde_json::json!({"id": 1, "title": "Dune", "author": "Frank Herbert", "genre": null, "language": null, "kind": "book", UPDATED_AT: 42, "version": 2});
+        let book: Book = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(book.updated_at, 42);
+        assert_eq!(serde_json::to_value(&book).unwrap(), json);
+
+        let stats = serde_json::to_value(BookStats {
+            total_books: 1,
+            distinct_authors: 1,
+            last_updated_at: None,
+        })
+        .unwrap();
+        let key = if cfg!(feature = "camel-case") { "totalBooks" } else { "total_books" };
+        assert!(stats.as_object().unwrap().contains_key(key));
+    }
+
+    #[actix_web::test]
+    async fn test_isbn_checksums() {
+        for valid in ["978-0-441-17271-9", "9780441172719", "0-15-602760-7", "0 8044 2957 X"] {
+            assert!(is_isbn(valid), "{}", valid);
+        }
+        for invalid in ["978-0-441-17271-8", "0-15-602760-8", "12345", "97804411727X9", ""] {
+            assert!(!is_isbn(invalid), "{}", invalid);
+        }
+    }
+}
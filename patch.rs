@@ -0,0 +1,132 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// One RFC 6902 JSON Patch operation; paths are RFC 6901 JSON Pointers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+// Apply operations in order to a copy of the document; on failure the document is untouched
+// and the error names the failing operation
+pub fn apply(document: &Value, operations: &[PatchOperation]) -> Result<Value, String> {
+    let mut patched = document.clone();
+    for (index, operation) in operations.iter().enumerate() {
+        apply_one(&mut patched, operation).map_err(|message| format!("operation {}: {}", index, message))?;
+    }
+    Ok(patched)
+}
+
+fn apply_one(document: &mut Value, operation: &PatchOperation) -> Result<(), String> {
+    match operation {
+        PatchOperation::Add { path, value } => add(document, path, value.clone()),
+        PatchOperation::Remove { path } => remove(document, path).map(drop),
+        PatchOperation::Replace { path, value } => {
+            let target = document.pointer_mut(path).ok_or_else(|| format!("path {:?} does not exist", path))?;
+            *target = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("cannot move {:?} into its own child {:?}", from, path));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = document.pointer(from).ok_or_else(|| format!("path {:?} does not exist", from))?.clone();
+            add(document, path, value)
+        }
+        PatchOperation::Test { path, value } => match document.pointer(path) {
+            Some(current) if current == value => Ok(()),
+            Some(_) => Err(format!("test failed: {:?} does not match", path)),
+            None => Err(format!("path {:?} does not exist", path)),
+        },
+    }
+}
+
+// Split a pointer into the pointer of its parent and its unescaped last token
+fn split(path: &str) -> Result<(&str, String), String> {
+    let (parent, last) = path.rsplit_once('/').ok_or_else(|| format!("invalid JSON pointer {:?}", path))?;
+    if !parent.is_empty() && !parent.starts_with('/') {
+        return Err(format!("invalid JSON pointer {:?}", path));
+    }
+    Ok((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    let (parent, last) = split(path)?;
+    match document.pointer_mut(parent) {
+        Some(Value::Object(fields)) => {
+            fields.insert(last, value);
+            Ok(())
+        }
+        Some(Value::Array(items)) if last == "-" => {
+            items.push(value);
+            Ok(())
+        }
+        Some(Value::Array(items)) => match last.parse::<usize>() {
+            Ok(index) if index <= items.len() => {
+                items.insert(index, value);
+                Ok(())
+            }
+            _ => Err(format!("array index {:?} is out of bounds", last)),
+        },
+        Some(_) => Err(format!("{:?} is neither an object nor an array", parent)),
+        None => Err(format!("path {:?} does not exist", parent)),
+    }
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent, last) = split(path)?;
+    let removed = match document.pointer_mut(parent) {
+        Some(Value::Object(fields)) => fields.remove(&last),
+        Some(Value::Array(items)) => match last.parse::<usize>() {
+            Ok(index) if index < items.len() => Some(items.remove(index)),
+            _ => None,
+        },
+        _ => None,
+    };
+    removed.ok_or_else(|| format!("path {:?} does not exist", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn test_operations_apply_in_order() {
+        let document = json!({"title": "Emma", "tags": ["classic"], "genre": "novel"});
+        let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "test", "path": "/title", "value": "Emma"},
+            {"op": "replace", "path": "/title", "value": "Persuasion"},
+            {"op": "add", "path": "/tags/-", "value": "romance"},
+            {"op": "add", "path": "/tags/0", "value": "austen"},
+            {"op": "move", "from": "/genre", "path": "/category"},
+            {"op": "copy", "from": "/title", "path": "/a~1b"},
+        ]))
+        .unwrap();
+        assert_eq!(
+            apply(&document, &operations).unwrap(),
+            json!({"title": "Persuasion", "tags": ["austen", "classic", "romance"], "category": "novel", "a/b": "Persuasion"})
+        );
+
+        let failing: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "remove", "path": "/title"},
+            {"op": "test", "path": "/genre", "value": "poetry"},
+        ]))
+        .unwrap();
+        assert_eq!(apply(&document, &failing).unwrap_err(), "operation 1: test failed: \"/genre\" does not match");
+    }
+}
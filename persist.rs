@@ -0,0 +1,158 @@
+use crate::models::Book;
+use crate::store::{Books, Store};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// On-disk layout: the books and the store's next id, which survives the deletion of the
+// highest-numbered book so its id is not handed out again after a restart
+#[derive(Serialize, Deserialize)]
+struct Snapshot<B> {
+    next_id: i32,
+    books: B,
+}
+
+// Load books from a JSON file, treating a missing file as an empty store. Files written by
+// older versions (a bare array of books, or records lacking newer fields) are read with
+// defaults and rewritten in the current format, so later loads see complete records
+pub fn load(path: &Path) -> io::Result<Store> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Store::default()),
+        Err(e) => return Err(e),
+    };
+    let raw: serde_json::Value = serde_json::from_str(&contents).map_err(invalid_data)?;
+    let snapshot: Snapshot<Vec<serde_json::Value>> = match &raw {
+        serde_json::Value::Array(books) => Snapshot { next_id: 0, books: books.clone() },
+        _ => serde_json::from_value(raw.clone()).map_err(invalid_data)?,
+    };
+    let books: Vec<Book> = snapshot.books.into_iter().map(serde_json::from_value).collect::<Result<_, _>>().map_err(invalid_data)?;
+    let store = Store::from(books).with_next_id(snapshot.next_id);
+    let current = serde_json::to_value(Snapshot { next_id: store.next_id(), books: store.books.as_slice() }).map_err(invalid_data)?;
+    if current != raw {
+        info!("upgrading {} records in {}", store.books.len(), path.display());
+        save(path, &store)?;
+    }
+    Ok(store)
+}
+
+// Write the store's books and next id to a JSON file
+pub fn save(path: &Path, store: &Store) -> io::Result<()> {
+    write_snapshot(path, &Snapshot { next_id: store.next_id(), books: store.books.as_slice() })
+}
+
+// Save the store if it changed since the last flush, returning whether it was written
+pub async fn flush(path: &Path, books: &Books) -> io::Result<bool> {
+    let store = books.read().await;
+    if !store.take_dirty() {
+        return Ok(false);
+    }
+    if let Err(e) = save(path, &store) {
+        // Leave the changes pending so the next flush retries them
+        store.mark_dirty();
+        return Err(e);
+    }
+    Ok(true)
+}
+
+// Flush the store to disk every interval until the runtime shuts down
+pub fn spawn_flusher(path: PathBuf, books: Books, interval: Duration) {
+    actix_web::rt::spawn(async move {
+        let mut ticks = actix_web::rt::time::interval(interval);
+        // The first tick completes immediately, right after the store was loaded
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            if let Err(e) = flush(&path, &books).await {
+                warn!("failed to flush books to {}: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+// Write through a temporary file so a crash mid-write never leaves a truncated file behind
+fn write_snapshot(path: &Path, snapshot: &Snapshot<&[Book]>) -> io::Result<()> {
+    let body = serde_json::to_vec_pretty(snapshot).map_err(invalid_data)?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, body)?;
+    fs::rename(&tmp, path)
+}
+
+fn invalid_data(e: serde_json::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_load_upgrades_legacy_file() {
+        let dir = std::env::temp_dir().join(format!("restapi-rust-persist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("books.json");
+        // Written before genre, updated_at and version existed
+        fs::write(&path, r#"[{"id": 1, "title": "Dune", "author": "Frank Herbert"}]"#).unwrap();
+
+        let store = load(&path).unwrap();
+        assert_eq!(store.books.len(), 1);
+        assert_eq!(store.books[0].title, "Dune");
+        assert_eq!(store.books[0].genre, None);
+        assert_eq!(store.books[0].version, 1);
+
+        let rewritten: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(rewritten["books"][0]["genre"], serde_json::Value::Null);
+        assert!(rewritten["books"][0].as_object().unwrap().contains_key("genre"));
+        assert_eq!(rewritten["books"][0]["version"], 1);
+        assert_eq!(rewritten["next_id"], 2);
+
+        // A current-format file loads unchanged
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let store = load(&path).unwrap();
+        assert_eq!(store.books[0].author, "Frank Herbert");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_flush_writes_only_after_changes() {
+        use crate::models::NewBook;
+        use std::sync::Arc;
+        use tokio::sync::RwLock;
+
+        let dir = std::env::temp_dir().join(format!("restapi-rust-flush-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("books.json");
+        let books: Books = Arc::new(RwLock::new(Store::default()));
+        assert!(!flush(&path, &books).await.unwrap());
+        assert!(!path.exists());
+
+        let new_book = NewBook {
+            title: "Dune".to_string(),
+            author: "Frank Herbert".to_string(),
+            genre: None,
+            language: None,
+            kind: None,
+            issue_number: None,
+            isbn: None,
+            published_year: None,
+        };
+        books.write().await.insert(&new_book, None, None);
+        assert!(flush(&path, &books).await.unwrap());
+        let on_disk: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk["books"], serde_json::to_value(&books.read().await.books).unwrap());
+        assert!(!flush(&path, &books).await.unwrap());
+
+        // Deleting the newest book doesn't give its id back, across a reload too
+        books.write().await.remove(1);
+        assert!(flush(&path, &books).await.unwrap());
+        let mut reloaded = load(&path).unwrap();
+        assert_eq!(reloaded.insert(&new_book, None, None).id, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+// SplitMix64 generator behind GET /books/random, registered as app data. Seeded from entropy
+// unless RANDOM_SEED is set, which makes the sequence of picks repeatable
+pub struct Rng {
+    state: AtomicU64,
+}
+
+impl Rng {
+    pub fn new(seed: Option<u64>) -> Self {
+        Rng {
+            state: AtomicU64::new(seed.unwrap_or_else(|| RandomState::new().hash_one(Instant::now()))),
+        }
+    }
+
+    pub fn next_u64(&self) -> u64 {
+        let mut z = self.state.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Index below `len`, which must not be zero; the modulo bias is negligible for store sizes
+    pub fn below(&self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
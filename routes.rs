@@ -0,0 +1,69 @@
+use crate::errors::{json_config, query_config};
+use crate::handlers::*;
+use crate::middleware::{head_as_get, require_api_key};
+use actix_web::middleware::from_fn;
+use actix_web::{guard, web};
+
+// Register all endpoints; fixed /books/... paths must come before /books/{id}
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(json_config())
+    .app_data(query_config())
+    .service(web::resource("/").route(web::get().to(api_index)))
+    .service(
+        web::resource("/books")
+            .route(web::get().to(get_books))
+            .route(web::post().to(create_book))
+            .route(web::patch().guard(guard::Header("content-type", JSON_PATCH)).to(batch_patch_books))
+            .route(web::patch().to(batch_update_books)),
+    )
+    .service(web::resource("/books/ids").route(web::get().to(get_book_ids)))
+    .service(web::resource("/books/bulk").route(web::post().to(bulk_create_books)))
+    .service(web::resource("/books/validate-isbns").route(web::post().to(validate_isbns)))
+    .service(web::resource("/books/import").route(web::post().to(import_books)))
+    .service(web::resource("/books/export.json").route(web::get().to(export_books_json)))
+    .service(web::resource("/books/changes").route(web::get().to(get_book_changes)))
+    .service(web::resource("/books/random").route(web::get().to(get_random_book)))
+    .service(web::resource("/books/recent").route(web::get().to(get_recent_books)))
+    .service(web::resource("/books/longest-titles").route(web::get().to(get_longest_titles)))
+    .service(web::resource("/books/browse").route(web::get().to(browse_books)))
+    .service(web::resource("/books/browse/index").route(web::get().to(browse_index)))
+    .service(web::resource("/books/schema").route(web::get().to(get_books_schema)))
+    .service(web::resource("/books/tag-genre").route(web::post().to(tag_genre)))
+    .service(web::resource("/books/reorder").route(web::post().to(reorder_books)))
+    .service(web::resource("/books/duplicates").route(web::get().to(get_duplicate_books)))
+    .service(web::resource("/books/lookup").route(web::get().to(lookup_book)))
+    .service(web::resource("/books/exists").route(web::post().to(books_exist)))
+    .service(web::resource("/books/incomplete").route(web::get().to(get_incomplete_books)))
+    .service(web::resource("/books/by-decade").route(web::get().to(count_books_by_decade)))
+    .service(web::resource("/books/count-by-author").route(web::get().to(count_books_by_author)))
+    .service(web::resource("/books/count").wrap(from_fn(head_as_get)).route(web::get().to(count_books)))
+    .service(web::resource("/books/stats").wrap(from_fn(head_as_get)).route(web::get().to(get_books_stats)))
+    .service(
+        web::resource("/books/{id}")
+            .route(web::get().to(get_book))
+            .route(web::put().to(update_book))
+            .route(web::delete().to(delete_book)),
+    )
+    .service(web::resource("/books/{id}/tags").route(web::post().to(update_book_tags)))
+    .service(web::resource("/books/{id}/history").route(web::get().to(get_book_history)))
+    .service(
+        web::resource("/books/{id}/cover")
+            .route(web::get().to(get_book_cover))
+            .route(web::put().to(put_book_cover)),
+    )
+    .service(web::resource("/books/{id}/similar").route(web::get().to(get_similar_books)))
+    .service(web::resource("/health").route(web::get().to(health)))
+    .service(web::resource("/authors/suggest").route(web::get().to(suggest_authors)))
+    .service(
+        web::scope("/admin")
+            .wrap(from_fn(require_api_key))
+            .route("/maintenance", web::post().to(set_maintenance))
+            .route("/log-level", web::post().to(set_log_level))
+            .route("/dedupe", web::post().to(dedupe_books))
+            .route("/jobs/{id}", web::get().to(get_job))
+            .route("/normalize-authors", web::post().to(normalize_authors))
+            .route("/reindex", web::post().to(reindex_books))
+            .route("/selfcheck", web::get().to(self_check))
+            .route("/warmup", web::post().to(warmup)),
+    );
+}
@@ -0,0 +1,215 @@
+use crate::models::Book;
+use std::collections::BTreeSet;
+#[cfg(feature = "search-index")]
+use std::collections::BTreeMap;
+
+// Split text into lowercase alphanumeric tokens
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+}
+
+// Inverted index from title and author tokens to positions in the store's book list
+#[cfg(feature = "search-index")]
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: BTreeMap<String, BTreeSet<usize>>,
+}
+
+#[cfg(feature = "search-index")]
+impl SearchIndex {
+    // Build an index over every book
+    pub fn build(books: &[Book]) -> Self {
+        let mut index = SearchIndex::default();
+        for (position, book) in books.iter().enumerate() {
+            index.add(position, book);
+        }
+        index
+    }
+
+    // Index a book stored at the given position
+    pub fn add(&mut self, position: usize, book: &Book) {
+        for token in book_tokens(book) {
+            self.postings.entry(token).or_default().insert(position);
+        }
+    }
+
+    // Drop a book's tokens, e.g. before re-adding it with changed fields
+    pub fn remove(&mut self, position: usize, book: &Book) {
+        for token in book_tokens(book) {
+            if let Some(positions) = self.postings.get_mut(&token) {
+                positions.remove(&position);
+                if positions.is_empty() {
+                    self.postings.remove(&token);
+                }
+            }
+        }
+    }
+
+    // Drop a book that is leaving the list at the given position; books after it move down one
+    // position, so their postings shift with them instead of the whole index being rebuilt
+    pub fn remove_at(&mut self, position: usize, book: &Book) {
+        self.remove(position, book);
+        for positions in self.postings.values_mut() {
+            if positions.range(position..).next().is_some() {
+                let later = positions.split_off(&position);
+                positions.extend(later.into_iter().map(|p| p - 1));
+            }
+        }
+    }
+
+    // Number of distinct tokens indexed
+    pub fn token_count(&self) -> usize {
+        self.postings.len()
+    }
+
+    // Positions of books that may contain the query as a substring of their title or author, or
+    // None when the query has no tokens to look up. Every query token must occur inside some
+    // indexed token, so this is a superset of the real matches and callers still verify each one
+    pub fn candidates(&self, query: &str) -> Option<BTreeSet<usize>> {
+        let mut candidates: Option<BTreeSet<usize>> = None;
+        for query_token in tokenize(query) {
+            let positions: BTreeSet<usize> = self
+                .postings
+                .iter()
+                .filter(|(token, _)| token.contains(query_token.as_str()))
+                .flat_map(|(_, positions)| positions.iter().copied())
+                .collect();
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&positions).copied().collect(),
+                None => positions,
+            });
+        }
+        candidates
+    }
+}
+
+// Stand-in when the index is compiled out; every search falls back to a full scan
+#[cfg(not(feature = "search-index"))]
+#[derive(Default)]
+pub struct SearchIndex;
+
+#[cfg(not(feature = "search-index"))]
+impl SearchIndex {
+    pub fn build(_books: &[Book]) -> Self {
+        SearchIndex
+    }
+
+    pub fn add(&mut self, _position: usize, _book: &Book) {}
+
+    pub fn remove(&mut self, _position: usize, _book: &Book) {}
+
+    pub fn remove_at(&mut self, _position: usize, _book: &Book) {}
+
+    pub fn token_count(&self) -> usize {
+        0
+    }
+
+    pub fn candidates(&self, _query: &str) -> Option<BTreeSet<usize>> {
+        None
+    }
+}
+
+#[cfg(feature = "search-index")]
+fn book_tokens(book: &Book) -> BTreeSet<String> {
+    tokenize(&book.title).chain(tokenize(&book.author)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{Book, NewBook};
+    use crate::store::Store;
+
+    const WORDS: [&str; 12] = ["dune", "emma", "frank", "herbert", "jane", "austen", "war", "peace", "leo", "tolstoy", "the", "of"];
+
+    // Small deterministic generator so failures are reproducible without extra dependencies
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % bound
+        }
+
+        fn phrase(&mut self) -> String {
+            let words: Vec<&str> = (0..1 + self.next(3)).map(|_| WORDS[self.next(WORDS.len())]).collect();
+            let phrase = words.join(if self.next(2) == 0 { " " } else { ", " });
+            if self.next(4) == 0 { phrase.to_uppercase() } else { phrase }
+        }
+
+        fn query(&mut self, books: &[Book]) -> String {
+            match self.next(3) {
+                // A slice of an existing title or author, possibly spanning a word boundary
+                0 if !books.is_empty() => {
+                    let book = &books[self.next(books.len())];
+                    let text: Vec<char> = if self.next(2) == 0 { book.title.chars().collect() } else { book.author.chars().collect() };
+                    let start = self.next(text.len());
+                    let end = start + 1 + self.next(text.len() - start);
+                    text[start..end].iter().collect()
+                }
+                1 => WORDS[self.next(WORDS.len())][..2].to_string(),
+                _ => self.phrase(),
+            }
+        }
+    }
+
+    fn linear_scan(store: &Store, query: &str) -> Vec<i32> {
+        let query = query.to_lowercase();
+        store
+            .books
+            .iter()
+            .filter(|b| b.title.to_lowercase().contains(&query) || b.author.to_lowercase().contains(&query))
+            .map(|b| b.id)
+            .collect()
+    }
+
+    fn indexed(store: &Store, query: &str) -> Vec<i32> {
+        let lowered = query.to_lowercase();
+        store
+            .search_candidates(query)
+            .into_iter()
+            .filter(|b| b.title.to_lowercase().contains(&lowered) || b.author.to_lowercase().contains(&lowered))
+            .map(|b| b.id)
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_index_matches_linear_scan_on_random_datasets() {
+        for seed in 0..20 {
+            let mut rng = Lcg(seed);
+            let mut store = Store::default();
+            for step in 0..200 {
+                match rng.next(5) {
+                    0 if !store.books.is_empty() => {
+                        let id = store.books[rng.next(store.books.len())].id;
+                        store.remove(id);
+                    }
+                    2 if store.books.len() > 1 => {
+                        let ids = [store.books[rng.next(store.books.len())].id, store.books[rng.next(store.books.len())].id];
+                        store.remove_many(&ids);
+                    }
+                    1 if !store.books.is_empty() => {
+                        let id = store.books[rng.next(store.books.len())].id;
+                        let title = rng.phrase();
+                        store.update(id, |book| book.title = title);
+                    }
+                    _ => {
+                        store.insert(&NewBook {
+                            title: rng.phrase(),
+                            author: rng.phrase(),
+                            genre: None,
+                            language: None,
+                            kind: None,
+                            issue_number: None,
+                            isbn: None,
+                            published_year: None,
+                        }, None, None);
+                    }
+                }
+                let query = rng.query(&store.books);
+                assert_eq!(indexed(&store, &query), linear_scan(&store, &query), "seed {} step {} query {:?}", seed, step, query);
+            }
+        }
+    }
+}
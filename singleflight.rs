@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+// Coalesces concurrent calls with the same key so only the first one runs; the others wait
+// for its result. Keys are forgotten once a call completes, so later calls run afresh
+pub struct SingleFlight<K, V> {
+    in_flight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        SingleFlight {
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    // Run `call` unless an identical call is already in flight, returning the shared result.
+    // If the running caller is cancelled, one of the waiting callers takes over
+    pub async fn run<F: Future<Output = V>>(&self, key: K, call: F) -> V {
+        let cell = self.in_flight.lock().unwrap().entry(key.clone()).or_default().clone();
+        let value = cell.get_or_init(|| call).await.clone();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.get(&key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+            in_flight.remove(&key);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[actix_web::test]
+    async fn test_concurrent_identical_calls_share_one_result() {
+        let flights: Arc<SingleFlight<&str, Arc<Vec<i32>>>> = Arc::new(SingleFlight::default());
+        let hits = Arc::new(AtomicUsize::new(0));
+        // Stands in for an expensive store query and counts how often it actually runs
+        let query = |hits: Arc<AtomicUsize>| async move {
+            hits.fetch_add(1, Ordering::SeqCst);
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+            Arc::new(vec![1, 2, 3])
+        };
+
+        let handles: Vec<_> = (0..50)
+            .map(|_| {
+                let (flights, hits) = (flights.clone(), hits.clone());
+                actix_web::rt::spawn(async move { flights.run("/books", query(hits)).await })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(*handle.await.unwrap(), vec![1, 2, 3]);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // Once the flight has landed the next call runs again
+        flights.run("/books", query(hits.clone())).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,440 @@
+use crate::models::{is_isbn, is_language_tag, now_millis, AuthorRewrite, Book, BookKind, ChangeKind, HistoryEntry, NewBook, Violation};
+use crate::search::SearchIndex;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// Failure of a store operation, mapped to an HTTP status by ApiError
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoreError {
+    NotFound,
+    // The in-memory store never produces these; they are for backends that can fail
+    #[allow(dead_code)]
+    Conflict,
+    #[allow(dead_code)]
+    Backend(String),
+    // The store could not be reached in time, e.g. its lock was contended
+    Timeout,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound => write!(f, "book not found"),
+            StoreError::Conflict => write!(f, "book conflicts with the stored state"),
+            StoreError::Backend(message) => write!(f, "store backend failed: {}", message),
+            StoreError::Timeout => write!(f, "store is busy, retry later"),
+        }
+    }
+}
+
+// Tenant, owner, normalized title and normalized author shared by duplicate books
+type DuplicateKey = (Option<String>, Option<String>, String, String);
+
+// In-memory storage for books and their change history
+#[derive(Default)]
+pub struct Store {
+    pub books: Vec<Book>,
+    // Append-only snapshots per book id, oldest first
+    history: HashMap<i32, Vec<HistoryEntry>>,
+    // Token index over books, kept in step by the mutating methods; changes made directly
+    // to `books` are only picked up by reindex
+    index: SearchIndex,
+    // Set by every recorded change and cleared by take_dirty, so persistence can skip no-op flushes
+    dirty: AtomicBool,
+    revision: Revision,
+    // Lowest id insert may hand out; it only grows, so ids of deleted books are never reissued
+    next_id: i32,
+}
+
+// Source of revisions, shared by every store so a replaced store never reuses one
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+// Identifies the stored state; a fresh value is taken on construction and on every change
+struct Revision(u64);
+
+impl Default for Revision {
+    fn default() -> Self {
+        Revision(NEXT_REVISION.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl From<Vec<Book>> for Store {
+    fn from(books: Vec<Book>) -> Self {
+        Store {
+            index: SearchIndex::build(&books),
+            next_id: books.iter().map(|b| b.id).max().unwrap_or(0) + 1,
+            books,
+            ..Default::default()
+        }
+    }
+}
+
+impl FromIterator<Book> for Store {
+    fn from_iter<I: IntoIterator<Item = Book>>(books: I) -> Self {
+        Store::from(books.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl Store {
+    // The id the next insert will use
+    pub fn next_id(&self) -> i32 {
+        self.next_id.max(1)
+    }
+
+    // Raise the next id, e.g. to one persisted past the highest stored id
+    pub fn with_next_id(mut self, next_id: i32) -> Self {
+        self.next_id = self.next_id.max(next_id);
+        self
+    }
+
+    // Add a new book with the next id, on behalf of an owner and tenant if known, and record its creation
+    pub fn insert(&mut self, new_book: &NewBook, owner: Option<String>, tenant_id: Option<String>) -> Book {
+        let id = self.next_id.max(1);
+        self.next_id = id + 1;
+        let book = Book {
+            id,
+            title: new_book.title.clone(),
+            author: new_book.author.clone(),
+            genre: new_book.genre.clone(),
+            language: new_book.language.clone(),
+            kind: new_book.kind.as_deref().and_then(BookKind::parse).unwrap_or_default(),
+            issue_number: new_book.issue_number,
+            position: None,
+            tags: Vec::new(),
+            isbn: new_book.isbn.clone(),
+            published_year: new_book.published_year,
+            updated_at: now_millis(),
+            version: 1,
+            owner,
+            tenant_id,
+        };
+        self.index.add(self.books.len(), &book);
+        self.books.push(book.clone());
+        self.record(ChangeKind::Created, &book);
+        book
+    }
+
+    // Add a book under its own id, e.g. from an import; the caller makes sure the id is free
+    pub fn insert_with_id(&mut self, book: Book) -> Book {
+        self.next_id = self.next_id.max(book.id + 1);
+        self.index.add(self.books.len(), &book);
+        self.books.push(book.clone());
+        self.record(ChangeKind::Created, &book);
+        book
+    }
+
+    // Apply a group of changes as one: when the closure fails, the books, their history and the
+    // search index are put back as they were before it ran
+    pub fn transaction<T, E, F: FnOnce(&mut Store) -> Result<T, E>>(&mut self, change: F) -> Result<T, E> {
+        let (books, history) = (self.books.clone(), self.history.clone());
+        let result = change(self);
+        if result.is_err() {
+            self.books = books;
+            self.history = history;
+            self.reindex();
+            // The restored books get a revision of their own, so nothing cached mid-change is served
+            self.revision = Revision::default();
+        }
+        result
+    }
+
+    // Modify a book in place, bumping its version and recording the new state
+    pub fn update<F: FnOnce(&mut Book)>(&mut self, id: i32, change: F) -> Option<Book> {
+        let position = self.books.iter().position(|b| b.id == id)?;
+        let book = &mut self.books[position];
+        self.index.remove(position, book);
+        change(book);
+        self.index.add(position, book);
+        book.updated_at = now_millis();
+        book.version += 1;
+        let book = book.clone();
+        self.record(ChangeKind::Updated, &book);
+        Some(book)
+    }
+
+    // Remove a book, keeping its history for auditing
+    pub fn remove(&mut self, id: i32) -> Option<Book> {
+        let index = self.books.iter().position(|b| b.id == id)?;
+        let book = self.books.remove(index);
+        self.index.remove_at(index, &book);
+        self.record(ChangeKind::Deleted, &book);
+        Some(book)
+    }
+
+    // Remove several books in one pass with a single reindex, keeping their history; returns
+    // the books that were stored
+    pub fn remove_many(&mut self, ids: &[i32]) -> Vec<Book> {
+        let ids: HashSet<i32> = ids.iter().copied().collect();
+        let (removed, kept): (Vec<Book>, Vec<Book>) = std::mem::take(&mut self.books).into_iter().partition(|b| ids.contains(&b.id));
+        self.books = kept;
+        if !removed.is_empty() {
+            self.reindex();
+        }
+        for book in &removed {
+            self.record(ChangeKind::Deleted, book);
+        }
+        removed
+    }
+
+    // Find books sharing a normalized title and author, keeping the lowest id of each group;
+    // books of different owners or tenants are never merged.
+    // Returns removed ids keyed by the surviving id; nothing is removed when dry_run is set
+    pub fn dedupe(&mut self, dry_run: bool) -> BTreeMap<i32, Vec<i32>> {
+        let removed: BTreeMap<i32, Vec<i32>> = self
+            .duplicate_groups()
+            .into_iter()
+            .map(|group| (group[0], group[1..].to_vec()))
+            .collect();
+        if !dry_run {
+            let ids: Vec<i32> = removed.values().flatten().copied().collect();
+            self.remove_many(&ids);
+        }
+        removed
+    }
+
+    // Rewrite authors that differ only in case or spacing to the spelling most of their books use,
+    // preferring mixed case (e.g. "J.R.R. Tolkien" over "j.r.r. tolkien") on a tie; each tenant is
+    // normalized on its own. Returns the rewrites; nothing changes when dry_run is set
+    pub fn normalize_authors(&mut self, dry_run: bool) -> Vec<AuthorRewrite> {
+        let mut groups: BTreeMap<(Option<String>, String), BTreeMap<&str, usize>> = BTreeMap::new();
+        for book in &self.books {
+            let key = (book.tenant_id.clone(), normalize(&book.author));
+            *groups.entry(key).or_default().entry(&book.author).or_default() += 1;
+        }
+        let rewrites: Vec<AuthorRewrite> = groups
+            .into_iter()
+            .filter(|(_, spellings)| spellings.len() > 1)
+            .filter_map(|((tenant_id, _), spellings)| {
+                let mixed_case = |author: &str| author.chars().any(char::is_uppercase) && author.chars().any(char::is_lowercase);
+                let (author, _) = spellings.iter().max_by_key(|(author, count)| (**count, mixed_case(author), std::cmp::Reverse(**author)))?;
+                let variants: Vec<&&str> = spellings.keys().filter(|variant| *variant != author).collect();
+                Some(AuthorRewrite {
+                    tenant_id,
+                    author: author.to_string(),
+                    books: variants.iter().map(|variant| spellings[**variant]).sum(),
+                    variants: variants.into_iter().map(|variant| variant.to_string()).collect(),
+                })
+            })
+            .collect();
+        if !dry_run {
+            for rewrite in &rewrites {
+                let ids: Vec<i32> = self
+                    .books
+                    .iter()
+                    .filter(|b| b.tenant_id == rewrite.tenant_id && rewrite.variants.contains(&b.author))
+                    .map(|b| b.id)
+                    .collect();
+                for id in ids {
+                    self.update(id, |book| book.author = rewrite.author.clone());
+                }
+            }
+        }
+        rewrites
+    }
+
+    // Ids of books sharing a normalized title, author, owner and tenant, ascending within
+    // each group and groups ordered by their lowest id; books without a duplicate are left out
+    pub fn duplicate_groups(&self) -> Vec<Vec<i32>> {
+        let mut groups: HashMap<DuplicateKey, Vec<i32>> = HashMap::new();
+        for book in &self.books {
+            let key = (book.tenant_id.clone(), book.owner.clone(), normalize(&book.title), normalize(&book.author));
+            groups.entry(key).or_default().push(book.id);
+        }
+        let mut groups: Vec<Vec<i32>> = groups.into_values().filter(|ids| ids.len() > 1).collect();
+        groups.iter_mut().for_each(|ids| ids.sort());
+        groups.sort();
+        groups
+    }
+
+    // Books that may contain the query, in store order; every book when the query has no tokens
+    pub fn search_candidates(&self, query: &str) -> Vec<&Book> {
+        match self.index.candidates(query) {
+            Some(positions) => positions.into_iter().filter_map(|position| self.books.get(position)).collect(),
+            None => self.books.iter().collect(),
+        }
+    }
+
+    // Rebuild the search index from the current books, returning the number of distinct tokens
+    pub fn reindex(&mut self) -> usize {
+        self.index = SearchIndex::build(&self.books);
+        self.index.token_count()
+    }
+
+    // Id of a book, other than those excluded, that would duplicate the given one: same
+    // normalized title and author, owner and tenant, as `duplicate_groups` counts them
+    pub fn duplicate_of(&self, book: &Book, exclude: &[i32]) -> Option<i32> {
+        self.books
+            .iter()
+            .find(|other| !exclude.contains(&other.id) && is_duplicate(other, book))
+            .map(|other| other.id)
+    }
+
+    // Books whose normalized title and author equal the given ones, in store order
+    pub fn matching<'a>(&'a self, title: &str, author: &str) -> impl Iterator<Item = &'a Book> + 'a {
+        let (title, author) = (normalize(title), normalize(author));
+        self.books.iter().filter(move |b| normalize(&b.title) == title && normalize(&b.author) == author)
+    }
+
+    // Invariants the write paths are meant to keep, checked book by book in store order
+    pub fn check(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let mut seen = HashSet::new();
+        for book in &self.books {
+            let mut problems = Vec::new();
+            if !seen.insert(book.id) {
+                problems.push("duplicate id".to_string());
+            }
+            if book.title.trim().is_empty() {
+                problems.push("empty title".to_string());
+            }
+            if book.author.trim().is_empty() {
+                problems.push("empty author".to_string());
+            }
+            if let Some(language) = book.language.as_deref().filter(|language| !is_language_tag(language)) {
+                problems.push(format!("invalid language tag {:?}", language));
+            }
+            if book.kind == BookKind::Magazine && book.issue_number.is_none() {
+                problems.push("magazine without an issue number".to_string());
+            }
+            if let Some(isbn) = book.isbn.as_deref().filter(|isbn| !is_isbn(isbn)) {
+                problems.push(format!("invalid ISBN {:?}", isbn));
+            }
+            violations.extend(problems.into_iter().map(|problem| Violation { id: book.id, problem }));
+        }
+        violations
+    }
+
+    // Chronological changes recorded for a book id
+    pub fn history(&self, id: i32) -> Option<&[HistoryEntry]> {
+        self.history.get(&id).map(Vec::as_slice)
+    }
+
+    // Last history entries of books deleted after the given time (in milliseconds since the Unix
+    // epoch) and not re-created since, oldest first
+    pub fn deleted_since(&self, since: u64) -> Vec<&HistoryEntry> {
+        let mut deleted: Vec<&HistoryEntry> = self
+            .history
+            .iter()
+            .filter(|(id, _)| !self.books.iter().any(|b| b.id == **id))
+            .filter_map(|(_, history)| history.last())
+            .filter(|entry| entry.change == ChangeKind::Deleted && entry.timestamp > since)
+            .collect();
+        deleted.sort_by_key(|entry| (entry.timestamp, entry.book.id));
+        deleted
+    }
+
+    // Whether anything changed since the last call, clearing the flag
+    pub fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::AcqRel)
+    }
+
+    // Flag the store as changed, e.g. when a flush of its changes failed
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    // Current revision; equal revisions mean identical books
+    pub fn revision(&self) -> u64 {
+        self.revision.0
+    }
+
+    fn record(&mut self, change: ChangeKind, book: &Book) {
+        self.mark_dirty();
+        self.revision = Revision::default();
+        self.history.entry(book.id).or_default().push(HistoryEntry {
+            change,
+            timestamp: now_millis(),
+            book: book.clone(),
+        });
+    }
+}
+
+// Whether two books share a normalized title and author, owner and tenant
+pub fn is_duplicate(a: &Book, b: &Book) -> bool {
+    a.owner == b.owner && a.tenant_id == b.tenant_id && normalize(&a.title) == normalize(&b.title) && normalize(&a.author) == normalize(&b.author)
+}
+
+// Case- and whitespace-insensitive form used to detect duplicates
+pub fn normalize(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+// Shared handle to the store
+pub type Books = Arc<RwLock<Store>>;
+
+lazy_static! {
+    pub static ref BOOKS: Books = Arc::new(RwLock::new(Store::default()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_book(title: &str, author: &str) -> NewBook {
+        NewBook {
+            title: title.to_string(),
+            author: author.to_string(),
+            genre: None,
+            language: None,
+            kind: None,
+            issue_number: None,
+            isbn: None,
+            published_year: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_failed_transaction_leaves_store_unchanged() {
+        let mut store = Store::default();
+        store.insert(&new_book("Dune", "Frank Herbert"), None, None);
+        let revision = store.revision();
+
+        let items = [new_book("Emma", "Jane Austen"), new_book("Ubik", "Philip K. Dick"), new_book("Solaris", "Stanislaw Lem")];
+        let result: Result<(), StoreError> = store.transaction(|store| {
+            store.update(1, |book| book.title = "Dune Messiah".to_string());
+            for (i, item) in items.iter().enumerate() {
+                // Forced failure on the last item, after the others went in
+                if i == items.len() - 1 {
+                    return Err(StoreError::Backend("write failed".to_string()));
+                }
+                store.insert(item, None, None);
+            }
+            Ok(())
+        });
+        assert_eq!(result, Err(StoreError::Backend("write failed".to_string())));
+        assert_eq!(store.books.iter().map(|b| b.title.as_str()).collect::<Vec<_>>(), ["Dune"]);
+        assert_eq!(store.books[0].version, 1);
+        assert_eq!(store.history(1).unwrap().len(), 1);
+        assert!(store.history(2).is_none());
+        // Without the index every book is a candidate, so only then is the rebuilt index visible here
+        #[cfg(feature = "search-index")]
+        {
+            assert!(store.search_candidates("emma").is_empty());
+            assert_eq!(store.search_candidates("dune").len(), 1);
+        }
+        assert_ne!(store.revision(), revision);
+
+        let result: Result<(), StoreError> = store.transaction(|store| {
+            store.insert(&items[0], None, None);
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(store.books.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_ids_of_deleted_books_are_not_reissued() {
+        let mut store = Store::default();
+        store.insert(&new_book("Dune", "Frank Herbert"), None, None);
+        let emma = store.insert(&new_book("Emma", "Jane Austen"), None, None);
+        store.remove(emma.id);
+        let ubik = store.insert(&new_book("Ubik", "Philip K. Dick"), None, None);
+        assert_eq!(ubik.id, 3);
+        assert_eq!(store.history(ubik.id).unwrap().len(), 1);
+        assert_eq!(store.history(emma.id).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+// Render a JSON value as an XML document under the given root element. Objects become child
+// elements named after their keys, array entries become <item> elements, and null is an empty
+// element; keys that are not valid element names (e.g. numeric ids) become <entry key="...">
+pub fn to_xml(root: &str, value: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    write_element(&mut out, root, value);
+    out
+}
+
+fn write_element(out: &mut String, name: &str, value: &Value) {
+    let (open, close) = if is_element_name(name) {
+        (format!("<{}", name), format!("</{}>", name))
+    } else {
+        (format!("<entry key=\"{}\"", escape(name)), "</entry>".to_string())
+    };
+    out.push_str(&open);
+    match value {
+        Value::Null => {
+            out.push_str("/>");
+            return;
+        }
+        Value::Bool(value) => out.push_str(&format!(">{}", value)),
+        Value::Number(value) => out.push_str(&format!(">{}", value)),
+        Value::String(value) => out.push_str(&format!(">{}", escape(value))),
+        Value::Array(items) => {
+            out.push('>');
+            for item in items {
+                write_element(out, "item", item);
+            }
+        }
+        Value::Object(fields) => {
+            out.push('>');
+            for (key, value) in fields {
+                write_element(out, key, value);
+            }
+        }
+    }
+    out.push_str(&close);
+}
+
+// Conservative subset of XML names: an ASCII letter or underscore, then letters, digits, '_', '-' or '.'
+fn is_element_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && !name.to_ascii_lowercase().starts_with("xml")
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_json_values_render_as_xml() {
+        let value = serde_json::json!({
+            "data": [{"id": 1, "title": "Pride & <Prejudice>", "genre": null, "tags": ["classic"]}],
+            "removed": {"1": [2, 3]},
+            "truncated": false,
+        });
+        assert_eq!(
+            to_xml("response", &value),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?><response>",
+                "<data><item><genre/><id>1</id><tags><item>classic</item></tags><title>Pride &amp; &lt;Prejudice&gt;</title></item></data>",
+                "<removed><entry key=\"1\"><item>2</item><item>3</item></entry></removed>",
+                "<truncated>false</truncated>",
+                "</response>",
+            )
+        );
+    }
+}